@@ -5,15 +5,36 @@
 //! 
 //! ```sql
 //! CREATE TABLE errors (
-//!     traceback   text    PRIMARY KEY,
-//!     message_id  bigint  NOT NULL,
-//!     occurrences int     DEFAULT 1
+//!     traceback_hash bytea     PRIMARY KEY,
+//!     traceback      bytea     NOT NULL,
+//!     message_id     bigint    NOT NULL,
+//!     occurrences    int       DEFAULT 1,
+//!     last_occurred  timestamp NOT NULL DEFAULT now()
 //! );
 //! ```
+//!
+//! Tracebacks are stored gzip compressed, and can be expired by running [`TracebackCleaner`].
+//!
+//! Tables created with the older schema, `traceback text PRIMARY KEY` without `traceback_hash` or
+//! `last_occurred`, can be migrated with the following (`sha256` needs PostgreSQL 11 or later).
+//! Tables which already have `traceback_hash` as the primary key only need the last three statements.
+//! Tracebacks from before the migration are left uncompressed, which the traceback button still handles.
+//!
+//! ```sql
+//! ALTER TABLE errors ADD COLUMN traceback_hash bytea;
+//! UPDATE errors SET traceback_hash = sha256(convert_to(traceback, 'UTF8'));
+//! ALTER TABLE errors DROP CONSTRAINT errors_pkey;
+//! ALTER TABLE errors ADD PRIMARY KEY (traceback_hash);
+//! ALTER TABLE errors ALTER COLUMN traceback TYPE bytea USING convert_to(traceback, 'UTF8');
+//! ALTER TABLE errors ALTER COLUMN traceback SET NOT NULL;
+//! ALTER TABLE errors ADD COLUMN last_occurred timestamp NOT NULL DEFAULT now();
+//! ```
+//!
+//! If the database is unavailable or a query fails, errors are still posted to the webhook and, if
+//! [`GnomeData::error_spool`] is set, spooled to disk until [`ErrorSpoolReconciler`] can
+//! add them to the database.
 
-use std::borrow::Cow;
-#[cfg(feature = "songbird")]
-use std::sync::Arc;
+use std::{borrow::Cow, io::{Read as _, Write as _}, sync::Arc};
 
 use anyhow::{Error, Result};
 use sha2::Digest;
@@ -22,8 +43,6 @@ use tracing::error;
 
 use poise::serenity_prelude as serenity;
 
-#[cfg(feature = "songbird")]
-use crate::{Framework, framework_to_context};
 use crate::{GnomeData, require, FrameworkContext, PoiseContextExt, Context};
 
 const VIEW_TRACEBACK_CUSTOM_ID: &str = "error::traceback::view";
@@ -42,7 +61,7 @@ struct ErrorRow {
 
 #[derive(sqlx::FromRow)]
 struct TracebackRow {
-    pub traceback: String
+    pub traceback: Vec<u8>
 }
 
 #[must_use]
@@ -56,6 +75,32 @@ fn hash(data: &[u8]) -> Vec<u8> {
     Vec::from(&*hasher.finalize())
 }
 
+fn traceback_button(c: &mut serenity::CreateComponents) -> &mut serenity::CreateComponents {
+    c.create_action_row(|a| a.create_button(|b| {b
+        .label("View Traceback")
+        .custom_id(VIEW_TRACEBACK_CUSTOM_ID)
+        .style(serenity::ButtonStyle::Danger)
+    }))
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompresses a stored traceback, returning tracebacks stored uncompressed before migrating as is.
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Ok(data.to_vec());
+    }
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
 pub async fn handle_unexpected<'a>(
     ctx: &serenity::Context,
     poise_context: FrameworkContext<'_, impl AsRef<GnomeData>>,
@@ -65,19 +110,59 @@ pub async fn handle_unexpected<'a>(
     author_name: Option<String>,
     icon_url: Option<String>
 ) -> Result<()> {
-    let data = poise_context.user_data.as_ref();
+    let shard_count = poise_context.shard_manager.lock().await.shards_instantiated().await.len();
+    report_unexpected(
+        ctx, poise_context.user_data.as_ref(), shard_count,
+        event, error, extra_fields,
+        author_name, icon_url
+    ).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn report_unexpected<'a>(
+    ctx: &serenity::Context,
+    data: &GnomeData,
+    shard_count: usize,
+    event: &'a str,
+    error: Error,
+    extra_fields: impl IntoIterator<Item = (&str, Cow<'a, str>, bool)>,
+    author_name: Option<String>,
+    icon_url: Option<String>
+) -> Result<()> {
     let error_webhook = &data.error_webhook;
 
-    let traceback = format!("{:?}", error);
+    let traceback = data.redactor.redact(&format!("{:?}", error)).into_owned();
 
     let traceback_hash = hash(traceback.as_bytes());
-    let mut conn = data.pool.acquire().await?;
+    let mut conn = match data.pool.acquire().await {
+        Ok(conn) => Some(conn),
+        Err(err) => {
+            tracing::warn!("Database unavailable, reporting error without deduplication: {:?}", err);
+            None
+        }
+    };
 
-    if let Some(ErrorRowWithOccurrences{message_id, occurrences}) = sqlx::query_as("
-        UPDATE errors SET occurrences = occurrences + 1
-        WHERE traceback_hash = $1
-        RETURNING message_id, occurrences
-    ").bind(traceback_hash.clone()).fetch_optional(&mut conn).await? {
+    let existing_error: Option<ErrorRowWithOccurrences> = if let Some(active_conn) = &mut conn {
+        let result = sqlx::query_as("
+            UPDATE errors SET occurrences = occurrences + 1, last_occurred = now()
+            WHERE traceback_hash = $1
+            RETURNING message_id, occurrences
+        ").bind(traceback_hash.clone()).fetch_optional(&mut *active_conn).await;
+
+        // Handled the same as an unavailable database, so the error is still posted
+        match result {
+            Ok(existing_error) => existing_error,
+            Err(err) => {
+                tracing::warn!("Database query failed, reporting error without deduplication: {:?}", err);
+                conn = None;
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(ErrorRowWithOccurrences{message_id, occurrences}) = existing_error {
         let message_id = serenity::model::id::MessageId(message_id as u64);
         let mut message = error_webhook.get_message(&ctx.http, message_id).await?;
         let embed = &mut message.embeds[0];
@@ -90,7 +175,7 @@ pub async fn handle_unexpected<'a>(
         ])}).await?;
     } else {
         let short_error = {
-            let mut long_err = error.to_string();
+            let mut long_err = data.redactor.redact(&error.to_string()).into_owned();
 
             // Avoid char boundary panics with utf8 chars
             let mut new_len = 256;
@@ -122,20 +207,20 @@ pub async fn handle_unexpected<'a>(
             blank_field(),
         ];
 
-        let shard_count = poise_context.shard_manager.lock().await.shards_instantiated().await.len();
         let after_fields = [
             ("CPU Usage (5 minutes)", Cow::Owned(cpu_usage), true),
             ("System Memory Usage", Cow::Owned(mem_usage), true),
             ("Shard Count", Cow::Owned(shard_count.to_string()), true),
         ];
 
+        let database_available = conn.is_some();
         let embed = serenity::model::channel::Embed::fake(|e| {
             before_fields.into_iter()
                 .chain(extra_fields)
                 .chain(after_fields)
                 .for_each(|(title, mut value, inline)| {
                     if value != "\u{200B}" {
-                        value = Cow::Owned(format!("`{value}`"));
+                        value = Cow::Owned(format!("`{}`", data.redactor.redact(&value)));
                     };
 
                     e.field(title, &*value, inline);
@@ -150,28 +235,58 @@ pub async fn handle_unexpected<'a>(
                 });
             }
 
-            e.footer(|f| f.text("This error has occurred 1 time!"));
+            e.footer(|f| f.text(if database_available {
+                "This error has occurred 1 time!"
+            } else {
+                "The database is unavailable, so this error has not been deduplicated!"
+            }));
             e.title(short_error);
             e.colour(crate::RED)
         });
 
-        let message = error_webhook.execute(&ctx.http, true, |b| {b
-            .embeds(vec![embed])
-            .components(|c| c.create_action_row(|a| a.create_button(|b| {b
-                .label("View Traceback")
-                .custom_id(VIEW_TRACEBACK_CUSTOM_ID)
-                .style(serenity::ButtonStyle::Danger)
-            })))
-        }).await?.unwrap();
+        let message = error_webhook.execute(&ctx.http, true, |b| {
+            // Attached so the traceback is available even if the database is not
+            if data.attach_tracebacks || !database_available {
+                b.add_file(serenity::AttachmentType::Bytes {
+                    data: Cow::Borrowed(traceback.as_bytes()),
+                    filename: String::from("traceback.txt")
+                });
+            }
+
+            b.embeds(vec![embed])
+            .components(traceback_button)
+            .allowed_mentions(|m| m.empty_parse())
+        }).await;
 
-        let ErrorRow{message_id} = sqlx::query_as("
+        let message = match message {
+            Ok(message) => message.unwrap(),
+            Err(err) => {
+                if !database_available {
+                    spool_traceback(data, None, &traceback).await?;
+                }
+
+                return Err(err.into())
+            }
+        };
+
+        let mut conn = require!(conn, spool_traceback(data, Some(message.id), &traceback).await);
+        let inserted: Result<ErrorRow, _> = sqlx::query_as("
             INSERT INTO errors(traceback_hash, traceback, message_id)
             VALUES($1, $2, $3)
 
             ON CONFLICT (traceback_hash)
-            DO UPDATE SET occurrences = errors.occurrences + 1
+            DO UPDATE SET occurrences = errors.occurrences + 1, last_occurred = now()
             RETURNING errors.message_id
-        ",).bind(traceback_hash).bind(traceback).bind(message.id.0 as i64).fetch_one(&mut conn).await?;
+        ",).bind(traceback_hash).bind(compress(traceback.as_bytes())?).bind(message.id.0 as i64).fetch_one(&mut conn).await;
+
+        // The message is already posted, so spool the traceback for it instead of losing it
+        let ErrorRow{message_id} = match inserted {
+            Ok(row) => row,
+            Err(err) => {
+                tracing::warn!("Database query failed, spooling traceback: {:?}", err);
+                return spool_traceback(data, Some(message.id), &traceback).await
+            }
+        };
 
         if message.id.0 != (message_id as u64) {
             error_webhook.delete_message(&ctx.http, message.id).await?;
@@ -181,6 +296,18 @@ pub async fn handle_unexpected<'a>(
     Ok(())
 }
 
+/// Writes a traceback to [`GnomeData::error_spool`], for [`ErrorSpoolReconciler`] to add to the database.
+async fn spool_traceback(data: &GnomeData, message_id: Option<serenity::MessageId>, traceback: &str) -> Result<()> {
+    let spool_dir = require!(&data.error_spool, Ok(()));
+    tokio::fs::create_dir_all(spool_dir).await?;
+
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_nanos();
+    let message_id = message_id.map(|id| id.0.to_string()).unwrap_or_default();
+
+    tokio::fs::write(spool_dir.join(format!("{timestamp}.traceback")), format!("{message_id}\n{traceback}")).await?;
+    Ok(())
+}
+
 pub async fn handle_unexpected_default(ctx: &serenity::Context, poise_context: FrameworkContext<'_, impl AsRef<GnomeData>>, name: &str, result: Result<()>) -> Result<()> {
     let error = require!(result.err(), Ok(()));
 
@@ -242,55 +369,234 @@ pub async fn handle_guild(name: &str, ctx: &serenity::Context, poise_context: Fr
 
 
 // Command Error handlers
-async fn handle_cooldown<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>, remaining_cooldown: std::time::Duration) -> Result<(), Error> {
-    let cooldown_response = ctx.send_error(
-        &ctx.gettext("{command_name} is on cooldown").replace("{command_name}", &ctx.command().name),
-        Some(&ctx.gettext("try again in {} seconds").replace("{}", &format!("{:.1}", remaining_cooldown.as_secs_f32())))
-    ).await?;
+/// Interaction tokens expire after 15 minutes, so reminders cannot be sent after this.
+const INTERACTION_TOKEN_LIFETIME: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Controls how [`ErrorHandler`] responds to [`poise::FrameworkError::CooldownHit`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CooldownOptions {
+    /// Shows the remaining cooldown as a Discord timestamp, which counts down live, and
+    /// edits the response of slash commands once the cooldown is over.
+    pub live_countdown: bool,
+    /// Offers slash command users a button to be pinged once the cooldown is over.
+    pub remind_button: bool,
+}
 
-    if let poise::Context::Prefix(ctx) = ctx {
-        if let Some(cooldown_response) = cooldown_response {
-            let ctx_discord = ctx.discord;
+async fn handle_cooldown<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>, remaining_cooldown: std::time::Duration, options: CooldownOptions) -> Result<(), Error> {
+    let command_name = &ctx.command().qualified_name;
+    let fix = if options.live_countdown {
+        let ready_at = std::time::SystemTime::now() + remaining_cooldown;
+        let ready_at = ready_at.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+
+        ctx.gettext("try again {}").replace("{}", &format!("<t:{ready_at}:R>"))
+    } else {
+        ctx.gettext("try again in {} seconds").replace("{}", &format!("{:.1}", remaining_cooldown.as_secs_f32()))
+    };
+
+    let cooldown_response = require!(ctx.send_error(
+        &ctx.gettext("{command_name} is on cooldown").replace("{command_name}", command_name),
+        Some(&fix)
+    ).await?, Ok(()));
+
+    match ctx {
+        poise::Context::Prefix(prefix_ctx) => {
+            let ctx_discord = prefix_ctx.discord;
             tokio::time::sleep(remaining_cooldown).await;
 
             let error_message = cooldown_response.into_message().await?;
             error_message.delete(ctx_discord).await?;
 
+            // DMs do not allow deleting other user's messages, so only guild channels are checked
             let bot_user_id = ctx_discord.cache.current_user_id();
-            let channel = error_message.channel(ctx_discord).await?.guild().unwrap();
+            if let Some(channel) = error_message.channel(ctx_discord).await?.guild() {
+                if channel.permissions_for_user(ctx_discord, bot_user_id)?.manage_messages() {
+                    prefix_ctx.msg.delete(ctx_discord).await?;
+                }
+            }
+        },
+        poise::Context::Application(_) => {
+            // Both the reminder and the edit need the interaction token, so there is nothing to wait for once it expires
+            if remaining_cooldown >= INTERACTION_TOKEN_LIFETIME {
+                return Ok(());
+            }
+
+            let reminded = if options.remind_button {
+                wait_for_remind_button(ctx, remaining_cooldown).await?
+            } else if options.live_countdown {
+                tokio::time::sleep(remaining_cooldown).await;
+                false
+            } else {
+                return Ok(());
+            };
 
-            if channel.permissions_for_user(ctx_discord, bot_user_id)?.manage_messages() {
-                ctx.msg.delete(ctx_discord).await?;
+            let ready_message = ctx.gettext("{command_name} is ready to use again!").replace("{command_name}", command_name);
+            if options.live_countdown {
+                cooldown_response.edit(ctx, |b| b.content(ready_message.clone())).await?;
             }
-        }
-    };
+
+            if reminded {
+                ctx.send(|b| b.content(format!("<@{}>: {ready_message}", ctx.author().id))).await?;
+            }
+        },
+    }
 
     Ok(())
 }
 
-async fn handle_argparse<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>, error: Box<dyn std::error::Error + Send + Sync>, input: Option<String>) -> Result<(), Error> {
-    let fix = None;
-    let mut reason = None;
+/// Sends a "Remind Me" button and waits for the cooldown to end, returning if the button was pressed.
+async fn wait_for_remind_button<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>, remaining_cooldown: std::time::Duration) -> Result<bool, Error> {
+    let ready_at = tokio::time::Instant::now() + remaining_cooldown;
+    let custom_id = format!("cooldown::remind::{}", ctx.id());
+
+    let button_message = ctx.send(|b| b
+        .ephemeral(true)
+        .content(ctx.gettext("Would you like to be reminded when you can use this command again?"))
+        .components(|c| c.create_action_row(|a| a.create_button(|b| {b
+            .label(ctx.gettext("Remind Me"))
+            .custom_id(custom_id.clone())
+            .style(serenity::ButtonStyle::Primary)
+        })))
+    ).await?;
+
+    let filter_id = custom_id.clone();
+    let interaction = serenity::CollectComponentInteraction::new(ctx.discord())
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(remaining_cooldown)
+        .filter(move |interaction| interaction.data.custom_id == filter_id)
+        .await;
+
+    let reminded = if let Some(interaction) = interaction {
+        interaction.create_interaction_response(ctx.discord(), |r| {r
+            .kind(serenity::InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|d| d
+                .content(ctx.gettext("I will remind you when this command is ready!"))
+                .components(|c| c)
+            )
+        }).await?;
 
-    if error.is::<serenity::MemberParseError>() {
-        reason = Some(ctx.gettext("I cannot find the member: `{}`"));
+        true
+    } else {
+        button_message.edit(ctx, |b| b
+            .content(ctx.gettext("This command is ready to use again!"))
+            .components(|c| c)
+        ).await?;
+
+        false
+    };
+
+    tokio::time::sleep_until(ready_at).await;
+    Ok(reminded)
+}
+
+/// Maps an argument parse error to a gettext message id, with `{}` being replaced by the user input.
+///
+/// Set as [`GnomeData::argparse_hook`] to handle the `FromStr` error types of a bot's own arguments.
+pub type ArgParseHook = fn(&(dyn std::error::Error + Send + Sync + 'static)) -> Option<&'static str>;
+
+/// Returns the reason and expected type for the argument parse errors we know about.
+fn argparse_reason(error: &(dyn std::error::Error + Send + Sync + 'static)) -> Option<(&'static str, &'static str)> {
+    Some(if error.is::<serenity::MemberParseError>() {
+        ("I cannot find the member: `{}`", "a member")
+    } else if error.is::<serenity::UserParseError>() || error.is::<serenity::UserIdParseError>() {
+        ("I cannot find the user: `{}`", "a user")
+    } else if error.is::<serenity::RoleParseError>() || error.is::<serenity::RoleIdParseError>() {
+        ("I cannot find the role: `{}`", "a role")
+    } else if error.is::<serenity::MessageParseError>() {
+        ("I cannot find the message: `{}`", "a message link or ID")
+    } else if error.is::<serenity::EmojiParseError>() || error.is::<serenity::EmojiIdentifierParseError>() {
+        ("I cannot find the emoji: `{}`", "an emoji")
     } else if error.is::<serenity::GuildParseError>() {
-        reason = Some(ctx.gettext("I cannot find the server: `{}`"));
-    } else if error.is::<serenity::GuildChannelParseError>() {
-        reason = Some(ctx.gettext("I cannot find the channel: `{}`"));
+        ("I cannot find the server: `{}`", "a server")
+    } else if error.is::<serenity::GuildChannelParseError>() || error.is::<serenity::ChannelParseError>() || error.is::<serenity::ChannelIdParseError>() {
+        ("I cannot find the channel: `{}`", "a channel")
     } else if error.is::<std::num::ParseIntError>() {
-        reason = Some(ctx.gettext("I cannot convert `{}` to a number"));
+        ("I cannot convert `{}` to a number", "a whole number")
+    } else if error.is::<std::num::ParseFloatError>() {
+        ("I cannot convert `{}` to a decimal number", "a number")
+    } else if error.is::<std::char::ParseCharError>() {
+        ("I cannot convert `{}` to a single character", "a single character")
     } else if error.is::<std::str::ParseBoolError>() {
-        reason = Some(ctx.gettext("I cannot convert `{}` to True/False"));
+        ("I cannot convert `{}` to True/False", "True/False")
+    } else {
+        return None
+    })
+}
+
+/// Finds the name of the slash command parameter that was given `input`, if possible.
+fn argparse_parameter<D>(ctx: Context<'_, D>, input: &str) -> Option<String> {
+    if let poise::Context::Application(ctx) = ctx {
+        if let poise::ApplicationCommandOrAutocompleteInteraction::ApplicationCommand(interaction) = ctx.interaction {
+            // Walk the options, including those of subcommands, to find the one the user typed
+            let mut options: Vec<_> = interaction.data.options.iter().collect();
+            while let Some(option) = options.pop() {
+                if let Some(value) = &option.value {
+                    if value.as_str().map_or_else(|| value.to_string(), str::to_owned) == input {
+                        return Some(option.name.clone())
+                    }
+                }
+
+                options.extend(&option.options);
+            }
+        }
     }
 
-    ctx.send_error(
-        reason.map(|r| r.replace("{}", &input.unwrap()).replace('`', "")).as_deref().unwrap_or("you typed the command wrong"),
-        Some(&fix.unwrap_or_else(|| ctx
-                .gettext("check out `/help {command}`")
-                .replace("{command}", &ctx.command().qualified_name)))
-    ).await?;
+    None
+}
+
+async fn handle_argparse<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>, error: Box<dyn std::error::Error + Send + Sync>, input: Option<String>) -> Result<(), Error> {
+    let (error, input) = match error.downcast::<poise::SlashArgError>() {
+        Ok(slash_error) => match *slash_error {
+            poise::SlashArgError::Parse { error, input } => (error, Some(input)),
+            poise::SlashArgError::CommandStructureMismatch(description) => {
+                tracing::warn!("Command structure mismatch in {}: {}", ctx.command().qualified_name, description);
+                ctx.send_error(
+                    ctx.gettext("Discord sent me outdated information about this command"),
+                    Some(ctx.gettext("wait a few minutes for Discord to update and try again"))
+                ).await?;
+
+                return Ok(())
+            },
+            slash_error => (Box::new(slash_error) as _, input),
+        },
+        Err(error) => (error, input),
+    };
+
+    let custom_reason = ctx.data().as_ref().argparse_hook.and_then(|hook| hook(&*error));
+    let (reason, expected) = match (custom_reason, argparse_reason(&*error)) {
+        (Some(reason), _) => (Some(reason), None),
+        (None, Some((reason, expected))) => (Some(reason), Some(expected)),
+        (None, None) => (None, None),
+    };
+
+    let parameter = input.as_deref().and_then(|input| argparse_parameter(ctx, input));
+    // Backticks are stripped to stop user input from breaking out of the formatting
+    let input = input.map(|i| i.replace('`', ""));
 
+    let reason = match (reason, &input) {
+        (Some(reason), Some(input)) => ctx.gettext(reason).replace("{}", input),
+        _ => ctx.gettext("you typed the command wrong").to_string(),
+    };
+
+    let help_fix = ctx.gettext("check out `/help {command}`").replace("{command}", &ctx.command().qualified_name);
+    let fix = match (expected, parameter) {
+        (Some(expected), Some(parameter)) => ctx
+            .gettext("provide {expected} for `{parameter}` or {help_fix}")
+            .replace("{expected}", ctx.gettext(expected))
+            .replace("{parameter}", &parameter)
+            .replace("{help_fix}", &help_fix),
+        (Some(expected), None) => ctx
+            .gettext("provide {expected} or {help_fix}")
+            .replace("{expected}", ctx.gettext(expected))
+            .replace("{help_fix}", &help_fix),
+        (None, Some(parameter)) => ctx
+            .gettext("check the value of `{parameter}` or {help_fix}")
+            .replace("{parameter}", &parameter)
+            .replace("{help_fix}", &help_fix),
+        (None, None) => help_fix,
+    };
+
+    ctx.send_error(&reason, Some(&fix)).await?;
     Ok(())
 }
 
@@ -313,7 +619,100 @@ const fn channel_type(channel: &serenity::Channel) -> &'static str {
     }
 }
 
+/// The variants of [`poise::FrameworkError`], used to pick which error an [`ErrorHandler`] override handles.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ErrorKind {
+    Setup,
+    Listener,
+    Command,
+    ArgumentParse,
+    CommandStructureMismatch,
+    CooldownHit,
+    MissingBotPermissions,
+    MissingUserPermissions,
+    NotAnOwner,
+    GuildOnly,
+    DmOnly,
+    NsfwOnly,
+    CommandCheckFailed,
+    DynamicPrefix,
+}
+
+impl<D> From<&poise::FrameworkError<'_, D, Error>> for ErrorKind {
+    fn from(error: &poise::FrameworkError<'_, D, Error>) -> Self {
+        match error {
+            poise::FrameworkError::Setup { .. } => Self::Setup,
+            poise::FrameworkError::Listener { .. } => Self::Listener,
+            poise::FrameworkError::Command { .. } => Self::Command,
+            poise::FrameworkError::ArgumentParse { .. } => Self::ArgumentParse,
+            poise::FrameworkError::CommandStructureMismatch { .. } => Self::CommandStructureMismatch,
+            poise::FrameworkError::CooldownHit { .. } => Self::CooldownHit,
+            poise::FrameworkError::MissingBotPermissions { .. } => Self::MissingBotPermissions,
+            poise::FrameworkError::MissingUserPermissions { .. } => Self::MissingUserPermissions,
+            poise::FrameworkError::NotAnOwner { .. } => Self::NotAnOwner,
+            poise::FrameworkError::GuildOnly { .. } => Self::GuildOnly,
+            poise::FrameworkError::DmOnly { .. } => Self::DmOnly,
+            poise::FrameworkError::NsfwOnly { .. } => Self::NsfwOnly,
+            poise::FrameworkError::CommandCheckFailed { .. } => Self::CommandCheckFailed,
+            poise::FrameworkError::DynamicPrefix { .. } => Self::DynamicPrefix,
+            poise::FrameworkError::__NonExhaustive => unreachable!(),
+        }
+    }
+}
+
+type ErrorOverride<D> = Box<dyn for<'a> Fn(poise::FrameworkError<'a, D, Error>) -> poise::BoxFuture<'a, Result<()>> + Send + Sync>;
+
+/// A [`poise::FrameworkError`] handler which falls back to the same handling as [`handle`] for any variant without an override.
+///
+/// As `on_error` is a function pointer, this should be stored in a static and called from there.
+pub struct ErrorHandler<D> {
+    overrides: std::collections::HashMap<ErrorKind, ErrorOverride<D>>,
+    cooldown_options: CooldownOptions,
+}
+
+impl<D> Default for ErrorHandler<D> {
+    fn default() -> Self {
+        Self {
+            overrides: std::collections::HashMap::new(),
+            cooldown_options: CooldownOptions::default(),
+        }
+    }
+}
+
+impl<D: AsRef<GnomeData> + Send + Sync> ErrorHandler<D> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the default handling of `kind` errors with `handler`.
+    #[must_use]
+    pub fn with_override<F>(mut self, kind: ErrorKind, handler: F) -> Self
+    where F: for<'a> Fn(poise::FrameworkError<'a, D, Error>) -> poise::BoxFuture<'a, Result<()>> + Send + Sync + 'static
+    {
+        self.overrides.insert(kind, Box::new(handler));
+        self
+    }
+
+    #[must_use]
+    pub fn with_cooldown_options(mut self, cooldown_options: CooldownOptions) -> Self {
+        self.cooldown_options = cooldown_options;
+        self
+    }
+
+    pub async fn handle(&self, error: poise::FrameworkError<'_, D, Error>) -> Result<(), Error> {
+        match self.overrides.get(&ErrorKind::from(&error)) {
+            Some(handler) => handler(error).await,
+            None => handle_default(error, self.cooldown_options).await,
+        }
+    }
+}
+
 pub async fn handle<D: AsRef<GnomeData> + Send + Sync>(error: poise::FrameworkError<'_, D, Error>) -> Result<(), Error> {
+    handle_default(error, CooldownOptions::default()).await
+}
+
+async fn handle_default<D: AsRef<GnomeData> + Send + Sync>(error: poise::FrameworkError<'_, D, Error>, cooldown_options: CooldownOptions) -> Result<(), Error> {
     match error {
         poise::FrameworkError::DynamicPrefix { error } => error!("Error in dynamic_prefix: {:?}", error),
         poise::FrameworkError::Command { error, ctx } => {
@@ -343,7 +742,7 @@ pub async fn handle<D: AsRef<GnomeData> + Send + Sync>(error: poise::FrameworkEr
             ctx.send_error("an unknown error occurred", None).await?;
         }
         poise::FrameworkError::ArgumentParse { error, ctx, input } => handle_argparse(ctx, error, input).await?,
-        poise::FrameworkError::CooldownHit { remaining_cooldown, ctx } => handle_cooldown(ctx, remaining_cooldown).await?,
+        poise::FrameworkError::CooldownHit { remaining_cooldown, ctx } => handle_cooldown(ctx, remaining_cooldown, cooldown_options).await?,
         poise::FrameworkError::MissingBotPermissions{missing_permissions, ctx} => {
             ctx.send_error(
                 &ctx.gettext("I cannot run `{command}` as I am missing permissions").replace("{command}", &ctx.command().name),
@@ -360,19 +759,37 @@ pub async fn handle<D: AsRef<GnomeData> + Send + Sync>(error: poise::FrameworkEr
             ).await?;
         },
 
-        poise::FrameworkError::Setup { error } => panic!("{:#?}", error),
+        // Override `ErrorKind::Setup` to panic or exit instead, if the bot cannot run without setup succeeding
+        poise::FrameworkError::Setup { error } => error!("Error in setup: {:?}", error),
         poise::FrameworkError::CommandCheckFailed { error, ctx } => {
             if let Some(error) = error {
-                error!("Premium Check Error: {:?}", error);
-                ctx.send_error(ctx.gettext("an unknown error occurred during the premium check"), None).await?;
+                error!("Command Check Error in {}: {:?}", ctx.command().qualified_name, error);
+                ctx.send_error(ctx.gettext("an unknown error occurred while checking if you can run this command"), None).await?;
             }
         },
 
-        poise::FrameworkError::Listener{..} => unreachable!("Listener error, but no listener???"),
-        poise::FrameworkError::CommandStructureMismatch {description: _, ctx: _} |
-        poise::FrameworkError::DmOnly {ctx: _ } |
-        poise::FrameworkError::NsfwOnly {ctx: _} | 
-        poise::FrameworkError::NotAnOwner{ctx: _} => {},
+        poise::FrameworkError::Listener { error, .. } => error!("Error in listener: {:?}", error),
+        poise::FrameworkError::CommandStructureMismatch { description, ctx } => {
+            tracing::warn!("Command structure mismatch in {}: {}", ctx.command.qualified_name, description);
+        },
+        poise::FrameworkError::NotAnOwner { ctx } => {
+            ctx.send_error(
+                &ctx.gettext("{command_name} can only be used by the bot owner").replace("{command_name}", &ctx.command().qualified_name),
+                None
+            ).await?;
+        },
+        poise::FrameworkError::DmOnly { ctx } => {
+            ctx.send_error(
+                &ctx.gettext("{command_name} can only be used in private messages").replace("{command_name}", &ctx.command().qualified_name),
+                Some(ctx.gettext("try running it in my DMs"))
+            ).await?;
+        },
+        poise::FrameworkError::NsfwOnly { ctx } => {
+            ctx.send_error(
+                &ctx.gettext("{command_name} can only be used in NSFW channels").replace("{command_name}", &ctx.command().qualified_name),
+                Some(ctx.gettext("try running it in a channel marked as NSFW"))
+            ).await?;
+        },
         poise::FrameworkError::GuildOnly {ctx} => {
             ctx.send_error(
                 &ctx.gettext("{command_name} cannot be used in private messages").replace("{command_name}", &ctx.command().qualified_name),
@@ -405,18 +822,20 @@ pub async fn handle_traceback_button(ctx: &serenity::Context, data: &GnomeData,
         .fetch_optional(&data.pool)
         .await?;
 
+    let traceback = row.map(|TracebackRow{traceback}| decompress(&traceback)).transpose()?;
+
     interaction.create_interaction_response(&ctx.http, |r| {r
         .kind(serenity::InteractionResponseType::ChannelMessageWithSource)
         .interaction_response_data(move |d| {
             d.ephemeral(true);
 
-            if let Some(TracebackRow{traceback}) = row {
+            if let Some(traceback) = traceback {
                 d.files([serenity::AttachmentType::Bytes {
-                    data: Cow::Owned(traceback.into_bytes()),
+                    data: Cow::Owned(traceback),
                     filename: String::from("traceback.txt")
                 }])
             } else {
-                d.content("No traceback found.")
+                d.content("No traceback found, it may have expired.")
             }
         })
     }).await?;
@@ -425,63 +844,260 @@ pub async fn handle_traceback_button(ctx: &serenity::Context, data: &GnomeData,
 }
 
 
-#[cfg(feature = "songbird")]
-struct TrackErrorHandler<D, Iter: IntoIterator<Item = (&'static str, Cow<'static, str>, bool)>> {
-    ctx: serenity::Context,
-    framework: Arc<Framework<D>>,
-    extra_fields: Iter,
-    author_name: String,
-    icon_url: String,
+/// Deletes stored tracebacks which have not occurred for the given expiry duration.
+pub struct TracebackCleaner {
+    pool: sqlx::PgPool,
+    expiry: std::time::Duration,
+}
+
+impl TracebackCleaner {
+    #[must_use]
+    pub fn new(pool: sqlx::PgPool, expiry: std::time::Duration) -> Self {
+        Self {pool, expiry}
+    }
 }
 
-#[cfg(feature = "songbird")]
 #[async_trait::async_trait]
-impl<D, Iter> songbird::EventHandler for TrackErrorHandler<D, Iter>
-where
-    Iter: IntoIterator<Item = (&'static str, Cow<'static, str>, bool)> + Clone + Send + Sync,
-    D: AsRef<GnomeData> + Send + Sync,
-{
-    async fn act(&self, ctx: &songbird::EventContext<'_>) -> Option<songbird::Event> {
-        if let songbird::EventContext::Track([(state, _)]) = ctx {
-            if let songbird::tracks::PlayMode::Errored(error) = state.playing.clone() {
-                let framework_context = framework_to_context(&self.framework, self.ctx.cache.current_user_id()).await;
-                let author_name = Some(self.author_name.clone());
-                let icon_url = Some(self.icon_url.clone());
-
-                let result = handle_unexpected(
-                    &self.ctx, framework_context,
-                    "TrackError", error.into(),
-                    self.extra_fields.clone(), author_name, icon_url
-                ).await;
-
-                if let Err(err_err) = result {
-                    tracing::error!("Songbird unhandled track error: {}", err_err);
-                }
+impl crate::Looper for TracebackCleaner {
+    const NAME: &'static str = "Traceback Cleaner";
+    const MILLIS: u64 = 1000 * 60 * 60;
+
+    async fn loop_func(&self) -> Result<()> {
+        let deleted = sqlx::query("DELETE FROM errors WHERE last_occurred < now() - make_interval(secs => $1)")
+            .bind(self.expiry.as_secs_f64())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        if deleted != 0 {
+            tracing::info!("{}: Deleted {} expired tracebacks", Self::NAME, deleted);
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Adds errors spooled by [`handle_unexpected`] while the database was unavailable to the database.
+pub struct ErrorSpoolReconciler<D> {
+    http: Arc<serenity::Http>,
+    data: Arc<D>,
+}
+
+impl<D> ErrorSpoolReconciler<D> {
+    #[must_use]
+    pub fn new(http: Arc<serenity::Http>, data: Arc<D>) -> Self {
+        Self {http, data}
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: AsRef<GnomeData> + Send + Sync> crate::Looper for ErrorSpoolReconciler<D> {
+    const NAME: &'static str = "Error Spool Reconciler";
+    const MILLIS: u64 = 1000 * 60;
+
+    async fn loop_func(&self) -> Result<()> {
+        let data = (*self.data).as_ref();
+        let spool_dir = require!(&data.error_spool, Ok(()));
+
+        let mut entries = match tokio::fs::read_dir(spool_dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Leave the spool alone until the database is back
+        let mut conn = require!(data.pool.acquire().await.ok(), Ok(()));
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension() != Some("traceback".as_ref()) {
+                continue
+            }
+
+            let spooled = tokio::fs::read_to_string(&path).await?;
+            let (message_id, traceback) = spooled.split_once('\n').unwrap_or(("", &spooled));
+
+            let message_id = if let Ok(message_id) = message_id.parse() {
+                serenity::MessageId(message_id)
+            } else {
+                // The webhook was also unavailable, so post a minimal report now
+                let embed = serenity::Embed::fake(|e| e
+                    .title("An error occurred while the database was unavailable")
+                    .colour(crate::RED)
+                );
+
+                let message_id = data.error_webhook.execute(&self.http, true, |b| b
+                    .embeds(vec![embed])
+                    .components(traceback_button)
+                    .allowed_mentions(|m| m.empty_parse())
+                ).await?.unwrap().id;
+
+                // Recorded before inserting, so the report is not posted again if the insert fails
+                tokio::fs::write(&path, format!("{message_id}\n{traceback}")).await?;
+                message_id
+            };
+
+            let ErrorRow{message_id: stored_message_id} = sqlx::query_as("
+                INSERT INTO errors(traceback_hash, traceback, message_id)
+                VALUES($1, $2, $3)
+
+                ON CONFLICT (traceback_hash)
+                DO UPDATE SET occurrences = errors.occurrences + 1, last_occurred = now()
+                RETURNING errors.message_id
+            ",).bind(hash(traceback.as_bytes())).bind(compress(traceback.as_bytes())?).bind(message_id.0 as i64).fetch_one(&mut conn).await?;
+
+            if message_id.0 != (stored_message_id as u64) {
+                data.error_webhook.delete_message(&self.http, message_id).await?;
             }
+
+            tokio::fs::remove_file(&path).await?;
         }
 
-        Some(songbird::Event::Cancel)
+        Ok(())
     }
 }
 
+
+/// A cheaply cloneable handle to report errors from outside of poise, such as from songbird events.
 #[cfg(feature = "songbird")]
-/// Registers a track to be handled by the error handler, arguments other than the
-/// track are passed to [`handle_unexpected`] if the track errors.
-pub fn handle_track<Iter, D>(
+pub struct ErrorReporter<D> {
     ctx: serenity::Context,
-    framework: Arc<Framework<D>>,
-    extra_fields: Iter,
-    author_name: String,
-    icon_url: String,
-
-    track: &songbird::tracks::TrackHandle
-) -> Result<(), songbird::error::ControlError>
-where
-    Iter: IntoIterator<Item = (&'static str, Cow<'static, str>, bool)> + Clone + Send + Sync + 'static,
-    D: AsRef<GnomeData> + Send + Sync + 'static,
-{
-    track.add_event(
-        songbird::Event::Track(songbird::TrackEvent::Error),
-        TrackErrorHandler {ctx, framework, extra_fields, author_name, icon_url}
-    )
+    data: Arc<D>,
+}
+
+#[cfg(feature = "songbird")]
+impl<D> Clone for ErrorReporter<D> {
+    fn clone(&self) -> Self {
+        Self {ctx: self.ctx.clone(), data: Arc::clone(&self.data)}
+    }
+}
+
+#[cfg(feature = "songbird")]
+impl<D: AsRef<GnomeData> + Send + Sync + 'static> ErrorReporter<D> {
+    #[must_use]
+    pub fn new(ctx: serenity::Context, data: Arc<D>) -> Self {
+        Self {ctx, data}
+    }
+
+    /// Reports an error via [`handle_unexpected`], using the cached shard count.
+    pub async fn report<'a>(
+        &self,
+        event: &'a str,
+        error: Error,
+        extra_fields: impl IntoIterator<Item = (&str, Cow<'a, str>, bool)>,
+        author_name: Option<String>,
+        icon_url: Option<String>
+    ) -> Result<()> {
+        let shard_count = self.ctx.cache.shard_count() as usize;
+        report_unexpected(
+            &self.ctx, (*self.data).as_ref(), shard_count,
+            event, error, extra_fields,
+            author_name, icon_url
+        ).await
+    }
+
+    /// Registers handlers on a songbird driver to report driver disconnects, track errors
+    /// and decode failures, with the guild and voice channel filled in.
+    pub fn register_driver(&self, driver: &mut songbird::Driver, guild_id: serenity::GuildId, channel_id: serenity::ChannelId) {
+        let handler = VoiceErrorHandler {
+            reporter: self.clone(),
+            guild_id,
+            channel_id: Arc::new(parking_lot::Mutex::new(channel_id)),
+        };
+
+        for event in [
+            songbird::Event::Track(songbird::TrackEvent::Error),
+            songbird::Event::Core(songbird::CoreEvent::DriverConnect),
+            songbird::Event::Core(songbird::CoreEvent::DriverReconnect),
+            songbird::Event::Core(songbird::CoreEvent::DriverDisconnect),
+        ] {
+            driver.add_global_event(event, handler.clone());
+        }
+    }
+}
+
+#[cfg(feature = "songbird")]
+struct VoiceErrorHandler<D> {
+    reporter: ErrorReporter<D>,
+    guild_id: serenity::GuildId,
+    channel_id: Arc<parking_lot::Mutex<serenity::ChannelId>>,
+}
+
+#[cfg(feature = "songbird")]
+impl<D> Clone for VoiceErrorHandler<D> {
+    fn clone(&self) -> Self {
+        Self {
+            reporter: self.reporter.clone(),
+            guild_id: self.guild_id,
+            channel_id: Arc::clone(&self.channel_id),
+        }
+    }
+}
+
+#[cfg(feature = "songbird")]
+impl<D: AsRef<GnomeData> + Send + Sync + 'static> VoiceErrorHandler<D> {
+    fn extra_fields(&self) -> Vec<(&'static str, Cow<'static, str>, bool)> {
+        let cache = &self.reporter.ctx.cache;
+        let channel_id = *self.channel_id.lock();
+
+        let mut extra_fields = Vec::with_capacity(4);
+        if let Some(guild_name) = cache.guild_field(self.guild_id, |g| g.name.clone()) {
+            extra_fields.push(("Guild", Cow::Owned(guild_name), true));
+        }
+
+        extra_fields.push(("Guild ID", Cow::Owned(self.guild_id.0.to_string()), true));
+        if let Some(channel_name) = cache.guild_channel_field(channel_id, |c| c.name.clone()) {
+            extra_fields.push(("Voice Channel", Cow::Owned(channel_name), true));
+        }
+
+        extra_fields.push(("Voice Channel ID", Cow::Owned(channel_id.0.to_string()), true));
+        extra_fields
+    }
+
+    async fn report(&self, event: &str, error: Error) {
+        if let Err(err_err) = self.reporter.report(event, error, self.extra_fields(), None, None).await {
+            tracing::error!("Songbird unhandled {} error: {:?}", event, err_err);
+        }
+    }
+}
+
+/// Converts a songbird ID to a serenity ID without requiring songbird's serenity feature.
+#[cfg(feature = "songbird")]
+fn songbird_channel_id(channel_id: songbird::id::ChannelId) -> Option<serenity::ChannelId> {
+    channel_id.to_string().parse().ok().map(serenity::ChannelId)
+}
+
+#[cfg(feature = "songbird")]
+#[async_trait::async_trait]
+impl<D: AsRef<GnomeData> + Send + Sync + 'static> songbird::EventHandler for VoiceErrorHandler<D> {
+    async fn act(&self, ctx: &songbird::EventContext<'_>) -> Option<songbird::Event> {
+        match ctx {
+            songbird::EventContext::Track(tracks) => {
+                for (state, _) in *tracks {
+                    if let songbird::tracks::PlayMode::Errored(error) = &state.playing {
+                        let event = if matches!(error, songbird::tracks::PlayError::Decode(_)) {"TrackDecodeError"} else {"TrackError"};
+                        self.report(event, anyhow::anyhow!("{:?}", error)).await;
+                    }
+                }
+            },
+            songbird::EventContext::DriverConnect(data) | songbird::EventContext::DriverReconnect(data) => {
+                if let Some(channel_id) = data.channel_id.and_then(songbird_channel_id) {
+                    *self.channel_id.lock() = channel_id;
+                }
+            },
+            songbird::EventContext::DriverDisconnect(data) => {
+                // No reason means the disconnect was requested, such as leaving the channel
+                if let Some(reason) = &data.reason {
+                    if let Some(channel_id) = data.channel_id.and_then(songbird_channel_id) {
+                        *self.channel_id.lock() = channel_id;
+                    }
+
+                    self.report("DriverDisconnect", anyhow::anyhow!("Driver disconnected ({:?}): {:?}", data.kind, reason)).await;
+                }
+            },
+            _ => {}
+        }
+
+        None
+    }
 }
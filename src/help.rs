@@ -3,7 +3,12 @@ use std::fmt::Write as _;
 use anyhow::Result;
 use indexmap::IndexMap;
 
-use crate::{Command, GnomeData, Context, require, ApplicationContext, PoiseContextExt};
+use crate::{serenity, traits::resolve_channel, Command, GnomeData, Context, require, ApplicationContext, PoiseContextExt};
+
+/// How long the help pagination buttons keep working after their last use.
+const HELP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const SELECT_MENU_OPTION_LIMIT: usize = 25;
 
 enum HelpCommandMode<'a, D: AsRef<GnomeData>> {
     Root,
@@ -11,11 +16,64 @@ enum HelpCommandMode<'a, D: AsRef<GnomeData>> {
     Command(&'a Command<D>),
 }
 
-fn get_command_mapping<D: AsRef<GnomeData>>(commands: &[Command<D>]) -> IndexMap<&str, Vec<&Command<D>>> {
+/// What the invoking user and channel allow, to hide or mark commands in help.
+///
+/// [`Command::checks`] are not run, as they may have side effects such as replying to the user.
+struct HelpFilter {
+    is_owner: bool,
+    in_guild: bool,
+    nsfw_channel: bool,
+    /// `None` if the permissions could not be resolved, in which case no commands are marked for them.
+    permissions: Option<serenity::Permissions>,
+}
+
+impl HelpFilter {
+    async fn new<D: AsRef<GnomeData> + Send + Sync>(ctx: Context<'_, D>) -> Self {
+        let nsfw_channel = match resolve_channel(ctx.discord(), ctx.guild_id(), ctx.channel_id()).await {
+            Ok(serenity::Channel::Guild(channel)) => channel.nsfw,
+            Ok(_) => false,
+            Err(err) => {
+                tracing::warn!("Failed to resolve channel for help: {:?}", err);
+                false
+            },
+        };
+
+        let permissions = match ctx.author_permissions().await {
+            Ok(permissions) => Some(permissions),
+            Err(err) => {
+                tracing::warn!("Failed to resolve permissions for help: {:?}", err);
+                None
+            },
+        };
+
+        Self {
+            nsfw_channel,
+            permissions,
+            in_guild: ctx.guild_id().is_some(),
+            is_owner: ctx.framework().options().owners.contains(&ctx.author().id),
+        }
+    }
+
+    /// If the command cannot be used by this user in this type of channel, so should not be shown.
+    fn is_hidden(&self, command: &Command<impl AsRef<GnomeData>>) -> bool {
+        command.hide_in_help
+            || (command.owners_only && !self.is_owner)
+            || (command.guild_only && !self.in_guild)
+            || (command.dm_only && self.in_guild)
+    }
+
+    /// If the command could be used, but not by this user in this channel, so is marked as unavailable.
+    fn is_restricted(&self, command: &Command<impl AsRef<GnomeData>>) -> bool {
+        (command.nsfw_only && !self.nsfw_channel)
+            || self.permissions.is_some_and(|permissions| !permissions.contains(command.required_permissions))
+    }
+}
+
+fn get_command_mapping<'a, D: AsRef<GnomeData>>(commands: &'a [Command<D>], filter: &HelpFilter) -> IndexMap<&'a str, Vec<&'a Command<D>>> {
     let mut mapping = IndexMap::new();
 
     for command in commands {
-        if !command.hide_in_help {
+        if !filter.is_hidden(command) {
             let commands = mapping
                 .entry(command.category.unwrap_or("Uncategoried"))
                 .or_insert_with(Vec::new);
@@ -27,29 +85,161 @@ fn get_command_mapping<D: AsRef<GnomeData>>(commands: &[Command<D>]) -> IndexMap
     mapping
 }
 
-fn format_params(command: &Command<impl AsRef<GnomeData>>) -> String {
+/// The locale of the interaction that invoked help, if it was invoked as a slash command or autocomplete.
+fn interaction_locale<'a>(ctx: Context<'a, impl AsRef<GnomeData>>) -> Option<&'a str> {
+    match ctx {
+        poise::Context::Application(ctx) => Some(match ctx.interaction {
+            poise::ApplicationCommandOrAutocompleteInteraction::ApplicationCommand(interaction) => interaction.locale.as_str(),
+            poise::ApplicationCommandOrAutocompleteInteraction::Autocomplete(interaction) => interaction.locale.as_str(),
+        }),
+        poise::Context::Prefix(_) => None,
+    }
+}
+
+/// Looks up command and parameter localizations for a locale, falling back to the defaults.
+struct Localiser<'a, D> {
+    commands: &'a [Command<D>],
+    locale: Option<&'a str>,
+}
+
+impl<'a, D> Localiser<'a, D> {
+    fn name<'b>(&self, command: &'b Command<D>) -> &'b str {
+        self.locale
+            .and_then(|locale| command.name_localizations.get(locale))
+            .map_or(command.name.as_str(), String::as_str)
+    }
+
+    fn description<'b>(&self, command: &'b Command<D>) -> Option<&'b str> {
+        self.locale
+            .and_then(|locale| command.description_localizations.get(locale))
+            .map(String::as_str)
+            .or(command.description.as_deref())
+    }
+
+    fn parameter_name<'b>(&self, parameter: &'b poise::CommandParameter<D, anyhow::Error>) -> &'b str {
+        self.locale
+            .and_then(|locale| parameter.name_localizations.get(locale))
+            .map_or(parameter.name.as_str(), String::as_str)
+    }
+
+    fn parameter_description<'b>(&self, parameter: &'b poise::CommandParameter<D, anyhow::Error>) -> Option<&'b str> {
+        self.locale
+            .and_then(|locale| parameter.description_localizations.get(locale))
+            .map(String::as_str)
+            .or(parameter.description.as_deref())
+    }
+
+    /// Localises each part of a qualified name, found by walking down from the root commands.
+    fn qualified_name(&self, qualified_name: &str) -> String {
+        let mut commands = self.commands;
+        qualified_name.split(' ').map(|name| match commands.iter().find(|c| c.name == name) {
+            Some(command) => {
+                commands = &command.subcommands;
+                self.name(command).to_string()
+            },
+            None => name.to_string(),
+        }).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn format_params<D>(command: &Command<D>, localiser: &Localiser<'_, D>) -> String {
     command.parameters.iter().map(|p| {
         if p.required {
-            format!("<{}> ", p.name)
+            format!("<{}> ", localiser.parameter_name(p))
         } else {
-            format!("[{}] ", p.name)
+            format!("[{}] ", localiser.parameter_name(p))
         }
     }).collect()
 }
 
-fn show_group_description(group: &IndexMap<&str, Vec<&Command<impl AsRef<GnomeData>>>>) -> String {
-    group.iter().map(|(category, commands)| {
-        format!("**__{category}__**\n{}\n", commands.iter().map(|c| {
-            let params = format_params(c);
-            if params.is_empty() {
-                format!("`{}`: {}\n", c.qualified_name, c.description.as_ref().unwrap())
-            } else {
-                format!("`{} {}`: {}\n", c.qualified_name, params, c.description.as_ref().unwrap())
+fn format_command<D: AsRef<GnomeData>>(command: &Command<D>, filter: &HelpFilter, localiser: &Localiser<'_, D>) -> String {
+    let params = format_params(command, localiser);
+    let name = localiser.qualified_name(&command.qualified_name);
+    let description = localiser.description(command).unwrap();
+    let lock = if filter.is_restricted(command) {"🔒 "} else {""};
+    if params.is_empty() {
+        format!("{lock}`{name}`: {description}\n")
+    } else {
+        format!("{lock}`{name} {params}`: {description}\n")
+    }
+}
+
+/// A page of help, listing the commands of one category.
+struct HelpPage {
+    category: usize,
+    description: String,
+}
+
+/// Splits a command mapping into pages by category, splitting categories further if they are too long for an embed.
+///
+/// Returns the category names with the index of their first page, and the pages.
+fn paginate<'a, D: AsRef<GnomeData>>(
+    group: &IndexMap<&'a str, Vec<&Command<D>>>,
+    filter: &HelpFilter,
+    localiser: &Localiser<'_, D>,
+) -> (Vec<(&'a str, usize)>, Vec<HelpPage>) {
+    let mut categories = Vec::new();
+    let mut pages: Vec<HelpPage> = Vec::new();
+
+    for (category_index, (category, commands)) in group.iter().enumerate() {
+        categories.push((*category, pages.len()));
+
+        let header = format!("**__{category}__**\n");
+        let mut description = header.clone();
+        for command in commands {
+            let line = format_command(command, filter, localiser);
+            if description.len() > header.len() && description.chars().count() + line.chars().count() > EMBED_DESCRIPTION_LIMIT {
+                pages.push(HelpPage {category: category_index, description: std::mem::replace(&mut description, header.clone())});
             }
-        }).collect::<String>()
-    )}).collect::<String>()
+
+            description.push_str(&line);
+        }
+
+        pages.push(HelpPage {category: category_index, description});
+    }
+
+    (categories, pages)
 }
 
+fn help_components(ctx: Context<'_, impl AsRef<GnomeData> + Send + Sync>, categories: &[(&str, usize)], pages: &[HelpPage], page: usize) -> serenity::CreateComponents {
+    let ctx_id = ctx.id();
+    let mut components = serenity::CreateComponents::default();
+
+    if categories.len() > 1 {
+        components.create_action_row(|a| a.create_select_menu(|s| s
+            .custom_id(format!("help::category::{ctx_id}"))
+            .placeholder(ctx.gettext("Select a category"))
+            .options(|o| {
+                for (index, (category, _)) in categories.iter().enumerate().take(SELECT_MENU_OPTION_LIMIT) {
+                    o.create_option(|opt| opt
+                        .label(category)
+                        .value(index)
+                        .default_selection(index == pages[page].category)
+                    );
+                }
+
+                o
+            })
+        ));
+    }
+
+    components.create_action_row(|a| a
+        .create_button(|b| b
+            .custom_id(format!("help::prev::{ctx_id}"))
+            .label(ctx.gettext("Previous"))
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page == 0)
+        )
+        .create_button(|b| b
+            .custom_id(format!("help::next::{ctx_id}"))
+            .label(ctx.gettext("Next"))
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page + 1 == pages.len())
+        )
+    );
+
+    components
+}
 
 pub async fn command(ctx: Context<'_, impl AsRef<GnomeData> + Send + Sync>, command: Option<&str>, neutral_colour: u32) -> Result<()> {
     let framework_options = ctx.framework().options();
@@ -90,75 +280,178 @@ pub async fn command(ctx: Context<'_, impl AsRef<GnomeData> + Send + Sync>, comm
         }
     };
 
-    ctx.send(|b| b.embed(|e| e
-        .title(ctx.gettext("{command_name} Help!").replace("{command_name}", &match &mode {
-            HelpCommandMode::Root => ctx.discord().cache.current_user_field(|u| u.name.clone()),
-            HelpCommandMode::Group(c) | HelpCommandMode::Command(c) => format!("`{}`", c.qualified_name) 
-        }))
-        .description(match &mode {
-            HelpCommandMode::Root => show_group_description(&get_command_mapping(commands)),
-            HelpCommandMode::Command(command_obj) => {
-                let mut msg = format!("{}\n```/{} {}```\n",
-                    command_obj.description.as_deref().unwrap_or_else(|| ctx.gettext("Command description not found!")),
-                    command_obj.qualified_name, format_params(command_obj),
-                );
-
-                if !command_obj.parameters.is_empty() {
-                    msg.push_str(ctx.gettext("__**Parameter Descriptions**__\n"));
-                    command_obj.parameters.iter().for_each(|p|
-                        writeln!(msg, "`{}`: {}", p.name, p.description.as_deref().unwrap_or_else(|| ctx.gettext("no description"))).unwrap()
-                    );
-                };
+    let localiser = Localiser {commands, locale: interaction_locale(ctx)};
+    let title = ctx.gettext("{command_name} Help!").replace("{command_name}", &match &mode {
+        HelpCommandMode::Root => ctx.discord().cache.current_user_field(|u| u.name.clone()),
+        HelpCommandMode::Group(c) | HelpCommandMode::Command(c) => format!("`{}`", localiser.qualified_name(&c.qualified_name))
+    });
 
-                msg
-            },
-            HelpCommandMode::Group(group) => show_group_description(&{
-                let mut map: IndexMap<&str, Vec<&Command<_>>> = IndexMap::new();
-                map.insert(&group.qualified_name, group.subcommands.iter().collect());
-                map
-            }),
-        })
-        .colour(neutral_colour)
-        .author(|a| {
-            a.name(ctx.author().name.clone());
-            a.icon_url(ctx.author().face())
-        })
-        .footer(|f| f.text(match mode {
-            HelpCommandMode::Group(c) => ctx
-                .gettext("Use `/help {command_name} [command]` for more info on a command")
-                .replace("{command_name}", &c.qualified_name),
-            HelpCommandMode::Command(_) |HelpCommandMode::Root => ctx
-                .gettext("Use `/help [command]` for more info on a command")
-                .to_string()
-        }))
-    )).await?;
+    let footer = match &mode {
+        HelpCommandMode::Group(c) => ctx
+            .gettext("Use `/help {command_name} [command]` for more info on a command")
+            // Not localised, as commands are only looked up by their default names
+            .replace("{command_name}", &c.qualified_name),
+        HelpCommandMode::Command(_) |HelpCommandMode::Root => ctx
+            .gettext("Use `/help [command]` for more info on a command")
+            .to_string()
+    };
+
+    let filter = HelpFilter::new(ctx).await;
+    let group_name;
+    let (categories, mut pages) = match &mode {
+        HelpCommandMode::Root => paginate(&get_command_mapping(commands, &filter), &filter, &localiser),
+        HelpCommandMode::Group(group) => paginate(&{
+            group_name = localiser.qualified_name(&group.qualified_name);
+
+            let mut map: IndexMap<&str, Vec<&Command<_>>> = IndexMap::new();
+            map.insert(&group_name, group.subcommands.iter().filter(|c| !filter.is_hidden(c)).collect());
+            map
+        }, &filter, &localiser),
+        HelpCommandMode::Command(command_obj) => {
+            let mut msg = format!("{}\n```/{} {}```\n",
+                localiser.description(command_obj).unwrap_or_else(|| ctx.gettext("Command description not found!")),
+                localiser.qualified_name(&command_obj.qualified_name), format_params(command_obj, &localiser),
+            );
+
+            if !command_obj.parameters.is_empty() {
+                msg.push_str(ctx.gettext("__**Parameter Descriptions**__\n"));
+                command_obj.parameters.iter().for_each(|p| writeln!(msg, "`{}`: {}",
+                    localiser.parameter_name(p),
+                    localiser.parameter_description(p).unwrap_or_else(|| ctx.gettext("no description"))
+                ).unwrap());
+            };
+
+            (Vec::new(), vec![HelpPage {category: 0, description: msg}])
+        },
+    };
+
+    // Every command may be hidden by the filter, such as guild only commands in DMs
+    if pages.is_empty() {
+        pages.push(HelpPage {category: 0, description: ctx.gettext("There are no commands available to you here!").to_string()});
+    }
+
+    let help_embed = |page: usize| {
+        let mut embed = serenity::CreateEmbed::default();
+        embed.title(&title)
+            .description(&pages[page].description)
+            .colour(neutral_colour)
+            .author(|a| {
+                a.name(ctx.author().name.clone());
+                a.icon_url(ctx.author().face())
+            })
+            .footer(|f| f.text(if pages.len() == 1 {
+                footer.clone()
+            } else {
+                let page_number = ctx.gettext("Page {page}/{page_count}")
+                    .replace("{page}", &(page + 1).to_string())
+                    .replace("{page_count}", &pages.len().to_string());
+
+                format!("{footer} • {page_number}")
+            }));
+
+        embed
+    };
+
+    let reply = ctx.send(|b| {
+        b.embed(|e| {*e = help_embed(0); e});
+        if pages.len() > 1 {
+            b.components(|c| {*c = help_components(ctx, &categories, &pages, 0); c});
+        }
+
+        b
+    }).await?;
+
+    if pages.len() == 1 {
+        return Ok(())
+    }
+
+    let ctx_id = ctx.id();
+    let prev_id = format!("help::prev::{ctx_id}");
+    let next_id = format!("help::next::{ctx_id}");
+    let custom_ids = [prev_id.clone(), next_id.clone(), format!("help::category::{ctx_id}")];
+
+    let mut page = 0;
+    loop {
+        let filter_ids = custom_ids.clone();
+        let interaction = serenity::CollectComponentInteraction::new(ctx.discord())
+            .author_id(ctx.author().id)
+            .channel_id(ctx.channel_id())
+            .timeout(HELP_TIMEOUT)
+            .filter(move |interaction| filter_ids.contains(&interaction.data.custom_id))
+            .await;
+
+        let Some(interaction) = interaction else {break};
+        let custom_id = &interaction.data.custom_id;
+        page = if *custom_id == prev_id {
+            page.saturating_sub(1)
+        } else if *custom_id == next_id {
+            (page + 1).min(pages.len() - 1)
+        } else {
+            interaction.data.values.first()
+                .and_then(|value| value.parse::<usize>().ok())
+                .and_then(|category| categories.get(category))
+                .map_or(page, |(_, first_page)| *first_page)
+        };
+
+        interaction.create_interaction_response(ctx.discord(), |r| r
+            .kind(serenity::InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|d| d
+                .embed(|e| {*e = help_embed(page); e})
+                .components(|c| {*c = help_components(ctx, &categories, &pages, page); c})
+            )
+        ).await?;
+    }
+
+    // Timed out, so remove the components as they would no longer work.
+    // Best effort, as the interaction token may have expired if help was used as a slash command.
+    if let Err(err) = reply.edit(ctx, |b| b.components(|c| c)).await {
+        tracing::debug!("Failed to remove help components: {:?}", err);
+    }
 
     Ok(())
 }
 
+/// Suggests commands by their localised name, with the default qualified name as the value to look them up by.
 #[allow(clippy::unused_async)]
-pub async fn autocomplete(ctx: ApplicationContext<'_, impl AsRef<GnomeData>>, searching: String) -> Vec<String> {
-    fn flatten_commands(commands: &[Command<impl AsRef<GnomeData>>], searching: &str) -> Vec<String> {
+pub async fn autocomplete<D: AsRef<GnomeData>>(ctx: ApplicationContext<'_, D>, searching: String) -> Vec<poise::AutocompleteChoice<String>> {
+    fn flatten_commands<D: AsRef<GnomeData>>(
+        commands: &[Command<D>],
+        filter: &HelpFilter,
+        localiser: &Localiser<'_, D>,
+        searching: &str,
+    ) -> Vec<poise::AutocompleteChoice<String>> {
         let mut result = Vec::new();
 
         for command in commands {
-            if command.owners_only || command.hide_in_help {
+            if filter.is_hidden(command) {
                 continue
             }
 
             if command.subcommands.is_empty() {
-                if command.qualified_name.starts_with(searching) {
-                    result.push(command.qualified_name.clone());
+                let name = localiser.qualified_name(&command.qualified_name);
+                if name.starts_with(searching) || command.qualified_name.starts_with(searching) {
+                    result.push(poise::AutocompleteChoice {name, value: command.qualified_name.clone()});
                 }
             } else {
-                result.extend(flatten_commands(&command.subcommands, searching));
+                result.extend(flatten_commands(&command.subcommands, filter, localiser, searching));
             }
         }
 
         result
     }
 
-    let mut result: Vec<String> = flatten_commands(&ctx.framework.options().commands, &searching);
-    result.sort_by_key(|a| strsim::levenshtein(a, &searching));
+    let commands = &ctx.framework.options().commands;
+    let localiser = Localiser {commands, locale: interaction_locale(poise::Context::Application(ctx))};
+
+    // Only used to hide commands, which does not depend on the channel or permissions
+    let filter = HelpFilter {
+        is_owner: ctx.framework.options().owners.contains(&poise::Context::Application(ctx).author().id),
+        in_guild: poise::Context::Application(ctx).guild_id().is_some(),
+        nsfw_channel: true,
+        permissions: None,
+    };
+
+    let mut result = flatten_commands(commands, &filter, &localiser, &searching);
+    result.sort_by_key(|choice| strsim::levenshtein(&choice.name, &searching));
     result
 }
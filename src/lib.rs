@@ -10,6 +10,7 @@ pub use poise::{self, serenity_prelude as serenity};
 #[cfg(feature = "analytics")] pub mod analytics;
 #[cfg(feature = "bot_list")] mod bot_list_updater;
 #[cfg(feature = "error_handling")] pub mod errors;
+#[cfg(any(feature = "logging", feature = "error_handling"))] pub mod redaction;
 mod macros;
 mod traits;
 mod looper;
@@ -22,6 +23,14 @@ pub use looper::Looper;
 
 #[allow(clippy::unreadable_literal)]
 pub const RED: u32 = 0xff0000;
+#[allow(clippy::unreadable_literal)]
+pub const GREEN: u32 = 0x00ff00;
+#[allow(clippy::unreadable_literal)]
+pub const ORANGE: u32 = 0xffa500;
+#[allow(clippy::unreadable_literal)]
+pub const BLUE: u32 = 0x3498db;
+#[allow(clippy::unreadable_literal)]
+pub const GREY: u32 = 0x95a5a6;
 
 #[cfg(feature = "poise")]
 pub type Command<D> = poise::Command<D, anyhow::Error>;
@@ -50,5 +59,53 @@ pub struct GnomeData {
     #[cfg(feature = "error_handling")] pub pool: sqlx::PgPool,
     #[cfg(feature = "error_handling")] pub error_webhook: serenity::Webhook,
     #[cfg(feature = "error_handling")] pub system_info: parking_lot::Mutex<sysinfo::System>,
+    #[cfg(feature = "error_handling")] pub argparse_hook: Option<errors::ArgParseHook>,
+    #[cfg(feature = "error_handling")] pub attach_tracebacks: bool,
+    #[cfg(feature = "error_handling")] pub error_spool: Option<std::path::PathBuf>,
+    #[cfg(feature = "error_handling")] pub theme: EmbedTheme,
+    #[cfg(feature = "error_handling")] pub redactor: redaction::Redactor,
     #[cfg(feature = "i18n")] pub translations: std::collections::HashMap<String, gettext::Catalog>,
 }
+
+/// The look of the embeds sent by [`PoiseContextExt`], with titles and footer being gettext message ids.
+#[cfg(feature = "error_handling")]
+#[derive(Debug, Clone)]
+pub struct EmbedTheme {
+    pub error_colour: u32,
+    pub success_colour: u32,
+    pub warning_colour: u32,
+    pub info_colour: u32,
+
+    pub error_title: &'static str,
+    pub success_title: &'static str,
+    pub warning_title: &'static str,
+    pub info_title: &'static str,
+
+    /// `{server_invite}` is replaced with [`GnomeData::main_server_invite`].
+    pub footer: &'static str,
+}
+
+#[cfg(feature = "error_handling")]
+impl Default for EmbedTheme {
+    fn default() -> Self {
+        Self {
+            error_colour: RED,
+            success_colour: GREEN,
+            warning_colour: ORANGE,
+            info_colour: BLUE,
+
+            error_title: "An Error Occurred!",
+            success_title: "Success!",
+            warning_title: "Warning!",
+            info_title: "Information",
+
+            footer: "Support Server: {server_invite}",
+        }
+    }
+}
+
+impl AsRef<GnomeData> for GnomeData {
+    fn as_ref(&self) -> &GnomeData {
+        self
+    }
+}
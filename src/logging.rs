@@ -1,25 +1,336 @@
 
-use std::{collections::HashMap, sync::Arc, fmt::Write};
+use std::{cell::RefCell, collections::{HashMap, VecDeque}, hash::Hash, path::PathBuf, sync::{Arc, atomic::{AtomicU64, Ordering}}, fmt::Write, time::{Duration, Instant}};
 
 use itertools::Itertools as _;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use anyhow::Result;
 
-use serenity::{http::Http, model::webhook::Webhook};
+use tokio::io::AsyncWriteExt as _;
+use serenity::{http::{Http, HttpError}, json::{json, JsonMap, Value}, model::{channel::Embed, id::WebhookId, webhook::Webhook, Timestamp}};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{filter::Filtered, layer::{self, Layer as _}, registry::LookupSpan};
 
-type LogMessage = (&'static str, String);
+#[derive(Clone)]
+struct LogMessage {
+    timestamp: Timestamp,
+    target: &'static str,
+    /// The value of the [`LinePrefix::shard_field`] of the innermost span with it.
+    shard: Option<String>,
+    /// The `outer{key=value}:inner` path of the spans the event was in, may be empty.
+    spans: String,
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+/// Everything in a [`LogMessage`] but the timestamp, so repeats of a message at different times compare equal.
+type LogMessageKey = (&'static str, Option<String>, String, String, Vec<(&'static str, String)>);
+
+impl LogMessage {
+    fn dedup_key(&self) -> LogMessageKey {
+        (self.target, self.shard.clone(), self.spans.clone(), self.message.clone(), self.fields.clone())
+    }
+}
+
+struct SpanData {
+    metadata: &'static tracing::Metadata<'static>,
+    parent: Option<tracing::span::Id>,
+    fields: Vec<(&'static str, String)>,
+    references: usize,
+}
+
+thread_local! {
+    // The spans entered on this thread, innermost last
+    static SPAN_STACK: RefCell<Vec<tracing::span::Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How the fields of an event, other than the message, are rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldStyle {
+    /// `message key=value key=value`
+    #[default]
+    Inline,
+    /// The message followed by a code block with a `key=value` line per field.
+    CodeBlock,
+    /// The message followed by a `- key: value` markdown list item per field.
+    ///
+    /// Event fields are never rendered as embed fields, as [`LoggerOptions::embeds`] uses a field per target.
+    BulletList,
+}
+
+/// Per-target maximum levels, in the style of `EnvFilter` directives.
+///
+/// Parsed from comma separated `target=level` pairs, such as `serenity=error,songbird=info,my_bot::tts=debug`.
+/// A lone level sets the level for targets without a directive, and a lone target enables all its levels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogDirectives {
+    default: LevelFilter,
+    // Sorted by descending target length, so the most specific directive is found first
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl LogDirectives {
+    #[must_use]
+    pub fn new(default: LevelFilter) -> Self {
+        Self {default, targets: Vec::new()}
+    }
+
+    #[must_use]
+    pub fn with_target(mut self, target: impl Into<String>, level: impl Into<LevelFilter>) -> Self {
+        let target = target.into();
+        self.targets.retain(|(t, _)| *t != target);
+        self.targets.push((target, level.into()));
+        self.targets.sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+        self
+    }
+
+    #[must_use]
+    pub fn max_level(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(t, _)| target.starts_with(t.as_str()))
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+impl std::str::FromStr for LogDirectives {
+    type Err = anyhow::Error;
+
+    fn from_str(directives: &str) -> Result<Self> {
+        let mut parsed = Self::new(LevelFilter::ERROR);
+        for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            parsed = match directive.split_once('=') {
+                Some((target, level)) => parsed.with_target(target.trim(), level.trim().parse::<LevelFilter>()
+                    .map_err(|_| anyhow::anyhow!("Invalid level in log directive: {directive}"))?
+                ),
+                None => match directive.parse::<LevelFilter>() {
+                    Ok(level) => Self {default: level, ..parsed},
+                    Err(_) => parsed.with_target(directive, LevelFilter::TRACE),
+                },
+            };
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Which log message is dropped when the buffer of pending logs is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drops the oldest pending message.
+    #[default]
+    DropOldest,
+    /// Drops the oldest pending message of the least severe level, or the new message if it is less severe.
+    DropLowestSeverity,
+}
+
+/// Counts of log messages dropped due to the buffer of pending logs being full.
+#[derive(Clone, Debug, Default)]
+pub struct DroppedLogs {
+    pub total: u64,
+    pub by_level: HashMap<tracing::Level, u64>,
+}
+
+/// Sends log messages of at least `level` severity, optionally only from a target prefix, to a set of webhooks.
+///
+/// A message is sent to every route it matches, and to the catch-all webhook if it matches none.
+#[derive(Clone, Debug)]
+pub struct LogRoute {
+    target: Option<String>,
+    level: LevelFilter,
+    webhooks: Vec<Webhook>,
+}
+
+impl LogRoute {
+    #[must_use]
+    pub fn new(level: impl Into<LevelFilter>, webhooks: impl IntoIterator<Item = Webhook>) -> Self {
+        Self {target: None, level: level.into(), webhooks: webhooks.into_iter().collect()}
+    }
+
+    #[must_use]
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    fn matches(&self, level: tracing::Level, target: &str) -> bool {
+        level <= self.level && self.target.as_ref().is_none_or(|t| target.starts_with(t.as_str()))
+    }
+}
+
+enum ChunkContent {
+    Text(String),
+    Embeds(Vec<Value>),
+}
+
+/// A chunk of log messages waiting to be sent to a webhook.
+struct PendingChunk {
+    content: ChunkContent,
+    username: String,
+    avatar_url: String,
+    attempts: u8,
+}
+
+/// The chunks waiting to be sent to a single webhook, kept between flushes so a failing webhook does not lose logs.
+#[derive(Default)]
+struct WebhookQueue {
+    chunks: VecDeque<PendingChunk>,
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+}
+
+const MESSAGE_LENGTH_LIMIT: usize = 2000;
+const EMBED_FIELD_NAME_LIMIT: usize = 256;
+const EMBED_FIELD_VALUE_LIMIT: usize = 1024;
+const EMBED_FIELD_LIMIT: usize = 25;
+const EMBED_TOTAL_LIMIT: usize = 6000;
+const EMBEDS_PER_MESSAGE: usize = 10;
+
+const MAX_SEND_ATTEMPTS: u8 = 5;
+const MAX_QUEUED_CHUNKS: usize = 500;
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// If sending to a webhook failed due to being ratelimited, Discord having issues, or a network error.
+fn is_transient(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(error) => match &**error {
+            HttpError::UnsuccessfulRequest(response) => {
+                response.status_code.as_u16() == 429 || response.status_code.is_server_error()
+            },
+            HttpError::Request(_) => true,
+            _ => false,
+        },
+        serenity::Error::Io(_) => true,
+        _ => false,
+    }
+}
+
+/// A local file for [`WebhookLogger`] to also write logs to, so they are kept when Discord is unavailable.
+///
+/// The file is rotated to `{path}.1`, shifting older files up to `{path}.{retention}`, once it is too large or old.
+#[derive(Clone, Debug)]
+pub struct FileSinkOptions {
+    pub path: PathBuf,
+    /// The size in bytes at which the file is rotated, or `None` to not rotate by size.
+    pub max_size: Option<u64>,
+    /// How long the file is written to before being rotated, or `None` to not rotate by time.
+    pub max_age: Option<Duration>,
+    /// How many rotated files are kept.
+    pub retention: usize,
+    /// Writes a JSON object per message, instead of the same lines as sent to Discord.
+    pub json_lines: bool,
+}
+
+impl FileSinkOptions {
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: Some(10 * 1024 * 1024),
+            max_age: None,
+            retention: 5,
+            json_lines: false,
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+}
+
+/// Moves the log file to `{path}.1`, shifting older rotated files up and overwriting the oldest.
+async fn rotate_log_file(options: &FileSinkOptions) -> std::io::Result<()> {
+    let ignore_missing = |result: std::io::Result<()>| match result {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    };
+
+    if options.retention == 0 {
+        return ignore_missing(tokio::fs::remove_file(&options.path).await);
+    }
+
+    for index in (1..options.retention).rev() {
+        ignore_missing(tokio::fs::rename(options.rotated_path(index), options.rotated_path(index + 1)).await)?;
+    }
+
+    ignore_missing(tokio::fs::rename(&options.path, options.rotated_path(1)).await)
+}
+
+/// What each line of a log message is prefixed with, in the order of the fields.
+#[derive(Clone, Debug)]
+pub struct LinePrefix {
+    /// The time the message was logged, shown in the reader's timezone unless in a code block.
+    pub timestamp: bool,
+    pub level: bool,
+    /// The name of a span field holding the shard id, such as `shard_id`, shown as `[shard N]` when found.
+    pub shard_field: Option<&'static str>,
+    pub target: bool,
+}
+
+impl Default for LinePrefix {
+    fn default() -> Self {
+        Self {timestamp: false, level: false, shard_field: None, target: true}
+    }
+}
+
+/// Formats the UTC time of day as `HH:MM:SS`.
+fn format_time_of_day(timestamp: Timestamp) -> String {
+    let seconds = timestamp.unix_timestamp().rem_euclid(24 * 60 * 60);
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggerOptions {
+    pub field_style: FieldStyle,
+    /// Overrides the default directives of `max_verbosity` for `gnomeutils` and `log_prefix`, and `warn` otherwise.
+    pub directives: Option<LogDirectives>,
+    /// The maximum number of log messages to hold between flushes, or `None` for no limit.
+    pub max_pending_logs: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    /// Sends logs as embeds coloured by severity, with a field per target, instead of plain text.
+    pub embeds: bool,
+    /// Wraps each message in a code block, so log text is shown verbatim instead of escaped.
+    pub code_blocks: bool,
+    /// Applied to every message, span, and event field before it is formatted.
+    pub redactor: crate::redaction::Redactor,
+    pub file_sink: Option<FileSinkOptions>,
+    pub line_prefix: LinePrefix,
+}
+
+impl Default for LoggerOptions {
+    fn default() -> Self {
+        Self {
+            field_style: FieldStyle::default(),
+            directives: None,
+            max_pending_logs: Some(10_000),
+            overflow_policy: OverflowPolicy::default(),
+            embeds: false,
+            code_blocks: false,
+            redactor: crate::redaction::Redactor::default(),
+            file_sink: None,
+            line_prefix: LinePrefix::default(),
+        }
+    }
+}
 
 pub struct WebhookLogger {
     http: Arc<Http>,
-    log_prefix: &'static str,
     webhook_name: &'static str,
-    max_verbosity: tracing::Level,
+    directives: RwLock<LogDirectives>,
     level_lookup: HashMap<tracing::Level, String>,
+    options: LoggerOptions,
 
-    pending_logs: Mutex<HashMap<tracing::Level, Vec<LogMessage>>>,
+    pending_logs: Mutex<VecDeque<(tracing::Level, LogMessage)>>,
+    dropped_logs: Mutex<DroppedLogs>,
+    dropped_since_flush: AtomicU64,
+    spans: Mutex<HashMap<tracing::span::Id, SpanData>>,
+    next_span_id: AtomicU64,
+    queues: Mutex<HashMap<WebhookId, WebhookQueue>>,
+    log_file_opened: Mutex<Option<Instant>>,
 
-    normal_logs: Webhook,
-    error_logs: Webhook,
+    routes: Vec<LogRoute>,
+    catch_all: Webhook,
+    webhooks: HashMap<WebhookId, Webhook>,
 }
 
 impl WebhookLogger {
@@ -32,6 +343,40 @@ impl WebhookLogger {
         normal_logs: Webhook,
         error_logs: Webhook,
     ) -> ArcWrapper<Self> {
+        Self::with_options(http, log_prefix, webhook_name, max_verbosity, normal_logs, error_logs, LoggerOptions::default())
+    }
+
+    /// Sends errors to `error_logs` and everything else to `normal_logs`.
+    #[must_use]
+    pub fn with_options(
+        http: Arc<Http>,
+        log_prefix: &'static str,
+        webhook_name: &'static str,
+        max_verbosity: tracing::Level,
+        normal_logs: Webhook,
+        error_logs: Webhook,
+        options: LoggerOptions,
+    ) -> ArcWrapper<Self> {
+        let routes = vec![LogRoute::new(tracing::Level::ERROR, [error_logs])];
+        Self::with_routes(http, log_prefix, webhook_name, max_verbosity, routes, normal_logs, options)
+    }
+
+    /// Sends each log message to the webhooks of every matching route, or `catch_all` if none match.
+    #[must_use]
+    pub fn with_routes(
+        http: Arc<Http>,
+        log_prefix: &'static str,
+        webhook_name: &'static str,
+        max_verbosity: tracing::Level,
+        routes: Vec<LogRoute>,
+        catch_all: Webhook,
+        mut options: LoggerOptions,
+    ) -> ArcWrapper<Self> {
+        let directives = options.directives.take().unwrap_or_else(|| LogDirectives::new(LevelFilter::WARN)
+            .with_target("gnomeutils", max_verbosity)
+            .with_target(log_prefix, max_verbosity)
+        );
+
         let level_lookup = HashMap::from_iter([
             (tracing::Level::TRACE, 1),
             (tracing::Level::DEBUG, 1),
@@ -40,11 +385,484 @@ impl WebhookLogger {
             (tracing::Level::ERROR, 4),
         ].map(|(level, value)| (level, format!("https://cdn.discordapp.com/embed/avatars/{value}.png"))));
 
+        let webhooks = routes.iter()
+            .flat_map(|route| &route.webhooks)
+            .chain(std::iter::once(&catch_all))
+            .map(|webhook| (webhook.id, webhook.clone()))
+            .collect();
+
         ArcWrapper(Arc::new(Self {
-            http, max_verbosity, level_lookup, normal_logs, error_logs, webhook_name, log_prefix,
+            http, level_lookup, routes, catch_all, webhooks, webhook_name, options,
+            directives: RwLock::new(directives),
             pending_logs: Mutex::default(),
+            dropped_logs: Mutex::default(),
+            dropped_since_flush: AtomicU64::new(0),
+            spans: Mutex::default(),
+            next_span_id: AtomicU64::new(1),
+            queues: Mutex::default(),
+            log_file_opened: Mutex::default(),
         }))
     }
+
+    /// Formats a message into lines, which are prefixed according to [`LinePrefix`], omitting the target if grouped
+    /// by target elsewhere. The log file always has the timestamp, level, and target.
+    ///
+    /// User controlled text is redacted, then for Discord escaped so it cannot render as markdown or break out of code blocks.
+    fn format_message(&self, level: tracing::Level, log_message: LogMessage, mode: FormatMode) -> String {
+        let LogMessage{timestamp, target, shard, spans, message, fields} = log_message;
+        let (include_target, code_blocks) = match mode {
+            FormatMode::Discord {include_target} => (include_target, self.options.code_blocks),
+            FormatMode::Plain => (true, true),
+        };
+
+        // Redacted before escaping, as escaping can split up the secrets the patterns match
+        let escape = |text: &str, in_code_block: bool| {
+            let redacted = self.options.redactor.redact(text);
+            if mode == FormatMode::Plain {
+                redacted.into_owned()
+            } else if in_code_block || code_blocks {
+                escape_code_block(&redacted)
+            } else {
+                escape_markdown(&redacted)
+            }
+        };
+
+        let mut message = escape(message.trim(), false);
+        if self.options.field_style == FieldStyle::Inline {
+            for (name, value) in &fields {
+                if !message.is_empty() {
+                    message.push(' ');
+                }
+
+                write!(message, "{name}={}", escape(value, false)).unwrap();
+            }
+        }
+
+        let plain = mode == FormatMode::Plain;
+        let line_prefix = &self.options.line_prefix;
+
+        let mut prefix = Vec::new();
+        if plain {
+            prefix.push(timestamp.to_string());
+        } else if line_prefix.timestamp {
+            prefix.push(if code_blocks {
+                format_time_of_day(timestamp)
+            } else {
+                format!("<t:{}:T>", timestamp.unix_timestamp())
+            });
+        }
+
+        if plain || line_prefix.level {
+            prefix.push(format!("{level:>5}"));
+        }
+
+        if let Some(shard) = shard {
+            prefix.push(format!("[shard {}]", escape(&shard, false)));
+        }
+
+        if include_target && (plain || line_prefix.target) {
+            prefix.push(if code_blocks {format!("[{target}]")} else {format!("`[{target}]`")});
+        }
+
+        if !spans.is_empty() {
+            prefix.push(escape(&spans, false));
+        }
+
+        let prefix = prefix.join(" ");
+        let mut formatted = String::new();
+        for line in message.lines() {
+            if prefix.is_empty() {
+                writeln!(formatted, "{line}").unwrap();
+            } else {
+                writeln!(formatted, "{prefix}: {line}").unwrap();
+            }
+        }
+
+        if fields.is_empty() {
+            return formatted
+        }
+
+        match (self.options.field_style, code_blocks) {
+            (FieldStyle::Inline, _) => {},
+            (FieldStyle::CodeBlock, false) => {
+                formatted.push_str("```\n");
+                for (name, value) in fields {
+                    writeln!(formatted, "{name}={}", escape(&value, true)).unwrap();
+                }
+                formatted.push_str("```\n");
+            },
+            // Already inside a code block, so indent the fields instead of nesting another
+            (FieldStyle::CodeBlock, true) => {
+                for (name, value) in fields {
+                    writeln!(formatted, "    {name}={}", escape(&value, true)).unwrap();
+                }
+            },
+            (FieldStyle::BulletList, false) => {
+                for (name, value) in fields {
+                    writeln!(formatted, "- `{name}`: {}", escape(&value, false)).unwrap();
+                }
+            },
+            (FieldStyle::BulletList, true) => {
+                for (name, value) in fields {
+                    writeln!(formatted, "- {name}: {}", escape(&value, true)).unwrap();
+                }
+            },
+        }
+
+        formatted
+    }
+
+    /// Splits text into chunks of at most `limit` characters, wrapping each in a code block if enabled.
+    fn chunk_text(&self, text: &str, limit: usize) -> Vec<String> {
+        if !self.options.code_blocks {
+            return chunk_lines(text, limit);
+        }
+
+        let fence_len = "```\n\n```".len();
+        chunk_lines(text, limit - fence_len).into_iter()
+            .map(|chunk| format!("```\n{}\n```", chunk.trim_end_matches('\n')))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FormatMode {
+    /// Markdown for a webhook message or embed, with the target omitted if messages are grouped by it.
+    Discord {include_target: bool},
+    /// Unescaped text, for the log file.
+    Plain,
+}
+
+/// Escapes markdown formatting characters, and breaks up mentions so they render as plain text.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '<' | '#' | '[' | ']' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            '@' => escaped.push_str("@\u{200B}"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Breaks up backticks, so text inside a code block cannot close it.
+fn escape_code_block(text: &str) -> String {
+    text.replace('`', "`\u{200B}")
+}
+
+/// Collapses messages with the same key into their first occurrence, along with how many times it occurred.
+fn dedup_messages<T, K: Eq + Hash>(messages: impl IntoIterator<Item = T>, key: impl Fn(&T) -> K) -> Vec<(T, usize)> {
+    let mut counted: Vec<(T, usize)> = Vec::new();
+    let mut indexes: HashMap<K, usize> = HashMap::new();
+
+    for message in messages {
+        let message_key = key(&message);
+        if let Some(&index) = indexes.get(&message_key) {
+            counted[index].1 += 1;
+        } else {
+            indexes.insert(message_key, counted.len());
+            counted.push((message, 1));
+        }
+    }
+
+    counted
+}
+
+/// Appends `(xN)` to the first line of a formatted message, if it occurred more than once.
+fn with_repeat_count(message: String, count: usize) -> String {
+    if count == 1 {
+        return message
+    }
+
+    // Appended to the first line, as the last line may be closing a code block
+    let (first_line, rest) = message.split_once('\n').unwrap_or((&message, ""));
+    format!("{first_line} (x{count})\n{rest}")
+}
+
+/// Splits lines into chunks of at most `limit` characters, splitting lines only if they are too long alone.
+///
+/// Code blocks split across chunks are closed at the end of one chunk and reopened at the start of the next.
+fn chunk_lines(text: &str, limit: usize) -> Vec<String> {
+    const OPEN_FENCE: &str = "```\n";
+    // Room to close a code block at the end of a chunk, with a newline first if split mid-line
+    const CLOSE_FENCE_LEN: usize = "\n```\n".len();
+
+    let mut chunks = Vec::new();
+    let mut current_chunk = String::new();
+    let mut current_len = 0;
+    let mut in_code_block = false;
+
+    for line in text.split_inclusive('\n') {
+        let is_fence = line.starts_with("```");
+        let piece_limit = if in_code_block {limit - OPEN_FENCE.len() - CLOSE_FENCE_LEN} else {limit};
+
+        for (index, piece) in line.chars().chunks(piece_limit).into_iter().map(Iterator::collect::<String>).enumerate() {
+            let piece_len = piece.chars().count();
+            let in_code_block_after = in_code_block ^ (is_fence && index == 0);
+            let reserved = if in_code_block_after {CLOSE_FENCE_LEN} else {0};
+
+            if current_len != 0 && current_len + piece_len + reserved > limit {
+                if in_code_block {
+                    if !current_chunk.ends_with('\n') {
+                        current_chunk.push('\n');
+                    }
+
+                    current_chunk.push_str(OPEN_FENCE);
+                }
+
+                chunks.push(std::mem::take(&mut current_chunk));
+                current_len = 0;
+
+                if in_code_block {
+                    current_chunk.push_str(OPEN_FENCE);
+                    current_len = OPEN_FENCE.len();
+                }
+            }
+
+            current_chunk.push_str(&piece);
+            current_len += piece_len;
+            in_code_block = in_code_block_after;
+        }
+    }
+
+    if !current_chunk.is_empty() {
+        chunks.push(current_chunk);
+    }
+
+    chunks
+}
+
+/// Packs `(name, value)` embed fields into messages of embeds, keeping within the field count of an embed, the
+/// embed count of a message, and the total character limit of a message's embeds.
+///
+/// `title_len` is the length of the title given to each embed, which counts towards the total.
+fn split_embeds(fields: Vec<(String, String)>, title_len: usize) -> Vec<Vec<Vec<(String, String)>>> {
+    let mut messages = Vec::new();
+    let mut embeds = Vec::new();
+    let mut current_fields: Vec<(String, String)> = Vec::new();
+    let mut message_len = 0;
+
+    for (name, value) in fields {
+        let field_len = name.chars().count() + value.chars().count();
+        if current_fields.len() == EMBED_FIELD_LIMIT {
+            embeds.push(std::mem::take(&mut current_fields));
+        }
+
+        let mut added_len = field_len + if current_fields.is_empty() {title_len} else {0};
+        let too_many_embeds = current_fields.is_empty() && embeds.len() == EMBEDS_PER_MESSAGE;
+        if too_many_embeds || message_len + added_len > EMBED_TOTAL_LIMIT {
+            if !current_fields.is_empty() {
+                embeds.push(std::mem::take(&mut current_fields));
+            }
+
+            messages.push(std::mem::take(&mut embeds));
+            message_len = 0;
+            added_len = field_len + title_len;
+        }
+
+        message_len += added_len;
+        current_fields.push((name, value));
+    }
+
+    if !current_fields.is_empty() {
+        embeds.push(current_fields);
+    }
+
+    if !embeds.is_empty() {
+        messages.push(embeds);
+    }
+
+    messages
+}
+
+fn severity_colour(severity: tracing::Level) -> u32 {
+    match severity {
+        tracing::Level::ERROR => crate::RED,
+        tracing::Level::WARN => crate::ORANGE,
+        tracing::Level::INFO => crate::BLUE,
+        _ => crate::GREY,
+    }
+}
+
+impl WebhookLogger {
+    fn format_json_line(&self, level: tracing::Level, log_message: &LogMessage) -> String {
+        let redact = |text: &str| self.options.redactor.redact(text).into_owned();
+        let fields: JsonMap = log_message.fields.iter()
+            .map(|(name, value)| ((*name).to_string(), Value::from(redact(value))))
+            .collect();
+
+        json!({
+            "timestamp": log_message.timestamp.to_string(),
+            "level": level.as_str(),
+            "target": log_message.target,
+            "shard": log_message.shard.as_deref().map(redact),
+            "spans": redact(&log_message.spans),
+            "message": redact(&log_message.message),
+            "fields": fields,
+        }).to_string()
+    }
+
+    /// Appends the messages to the log file, rotating it first if it is too large or old.
+    async fn write_log_file(&self, file_sink: &FileSinkOptions, logs: &VecDeque<(tracing::Level, LogMessage)>) -> std::io::Result<()> {
+        if logs.is_empty() {
+            return Ok(());
+        }
+
+        let mut output = String::new();
+        for (level, log_message) in logs {
+            if file_sink.json_lines {
+                output.push_str(&self.format_json_line(*level, log_message));
+                output.push('\n');
+            } else {
+                output.push_str(&self.format_message(*level, log_message.clone(), FormatMode::Plain));
+            }
+        }
+
+        let size = match tokio::fs::metadata(&file_sink.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+
+        let opened = *self.log_file_opened.lock().get_or_insert_with(Instant::now);
+        let too_large = file_sink.max_size.is_some_and(|max_size| size != 0 && size + output.len() as u64 > max_size);
+        let too_old = file_sink.max_age.is_some_and(|max_age| opened.elapsed() >= max_age);
+        if too_large || too_old {
+            rotate_log_file(file_sink).await?;
+            *self.log_file_opened.lock() = Some(Instant::now());
+        }
+
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&file_sink.path).await?;
+        file.write_all(output.as_bytes()).await?;
+        file.flush().await
+    }
+
+    /// Groups messages by target into embed fields, then packs them into as many messages as needed.
+    fn embed_chunks(&self, severity: tracing::Level, messages: Vec<(&'static str, String)>) -> Vec<ChunkContent> {
+        let mut by_target: Vec<(&'static str, Vec<String>)> = Vec::new();
+        for (target, message) in messages {
+            match by_target.iter_mut().find(|(t, _)| *t == target) {
+                Some((_, target_messages)) => target_messages.push(message),
+                None => by_target.push((target, vec![message])),
+            }
+        }
+
+        let fields = by_target.into_iter().flat_map(|(target, messages)| {
+            let name: String = target.chars().take(EMBED_FIELD_NAME_LIMIT).collect();
+            self.chunk_text(&messages.concat(), EMBED_FIELD_VALUE_LIMIT)
+                .into_iter()
+                .map(move |value| (name.clone(), value))
+        }).collect();
+
+        let title = severity.as_str();
+        let timestamp = Timestamp::now();
+        split_embeds(fields, title.len()).into_iter().map(|embeds| ChunkContent::Embeds(embeds
+            .into_iter()
+            .map(|fields| Embed::fake(|e| e
+                .title(title)
+                .colour(severity_colour(severity))
+                .timestamp(timestamp)
+                .fields(fields.into_iter().map(|(name, value)| (name, value, false)))
+            ))
+            .collect()
+        )).collect()
+    }
+
+    fn destinations(&self, level: tracing::Level, target: &str) -> Vec<WebhookId> {
+        let mut destinations: Vec<_> = self.routes.iter()
+            .filter(|route| route.matches(level, target))
+            .flat_map(|route| route.webhooks.iter().map(|webhook| webhook.id))
+            .unique()
+            .collect();
+
+        if destinations.is_empty() {
+            destinations.push(self.catch_all.id);
+        }
+
+        destinations
+    }
+
+    fn enqueue(&self, webhook_id: WebhookId, chunks: impl Iterator<Item = PendingChunk>) {
+        let mut queues = self.queues.lock();
+        let queue = queues.entry(webhook_id).or_default();
+        queue.chunks.extend(chunks);
+
+        let overflow = queue.chunks.len().saturating_sub(MAX_QUEUED_CHUNKS);
+        if overflow != 0 {
+            queue.chunks.drain(..overflow);
+            eprintln!("Dropped {overflow} queued log chunks for webhook {webhook_id} as its queue was full");
+        }
+    }
+
+    /// Sends the queued chunks for a webhook in order, stopping at the first transient failure to retry next flush.
+    ///
+    /// Serenity's ratelimiter already waits out the `retry-after` of ratelimit buckets it knows about, so
+    /// any 429 that makes it back here is treated as a transient failure and backed off exponentially.
+    ///
+    /// Failures are reported to stderr, as logging them would feed back into this logger.
+    async fn deliver(&self, webhook: &Webhook) {
+        let Some(mut queue) = self.queues.lock().remove(&webhook.id) else {
+            return;
+        };
+
+        if queue.retry_at.is_some_and(|retry_at| retry_at > Instant::now()) {
+            self.requeue(webhook.id, queue);
+            return;
+        }
+
+        while let Some(mut chunk) = queue.chunks.pop_front() {
+            let result = webhook.execute(&self.http, false, |b| {
+                match &chunk.content {
+                    ChunkContent::Text(content) => b.content(content),
+                    ChunkContent::Embeds(embeds) => b.embeds(embeds.clone()),
+                };
+
+                b.username(&chunk.username)
+                    .avatar_url(&chunk.avatar_url)
+                    .allowed_mentions(|m| m.empty_parse())
+            }).await;
+
+            let Err(error) = result else {
+                queue.consecutive_failures = 0;
+                queue.retry_at = None;
+                continue;
+            };
+
+            chunk.attempts += 1;
+            if !is_transient(&error) {
+                eprintln!("Failed to send log chunk to webhook {}, dropping it: {error}", webhook.id);
+                continue;
+            }
+
+            if chunk.attempts >= MAX_SEND_ATTEMPTS {
+                eprintln!("Failed to send log chunk to webhook {} after {MAX_SEND_ATTEMPTS} attempts, dropping it: {error}", webhook.id);
+            } else {
+                eprintln!("Failed to send log chunk to webhook {}, retrying later: {error}", webhook.id);
+                queue.chunks.push_front(chunk);
+            }
+
+            queue.consecutive_failures += 1;
+            let backoff = 2_u64.saturating_pow(queue.consecutive_failures).min(MAX_BACKOFF_SECS);
+            queue.retry_at = Some(Instant::now() + Duration::from_secs(backoff));
+            break;
+        }
+
+        self.requeue(webhook.id, queue);
+    }
+
+    fn requeue(&self, webhook_id: WebhookId, mut queue: WebhookQueue) {
+        let mut queues = self.queues.lock();
+        if let Some(newer) = queues.remove(&webhook_id) {
+            queue.chunks.extend(newer.chunks);
+        }
+
+        queues.insert(webhook_id, queue);
+    }
 }
 
 #[async_trait::async_trait]
@@ -53,37 +871,49 @@ impl crate::looper::Looper for WebhookLogger {
     const MILLIS: u64 = 1100;
 
     async fn loop_func(&self) -> Result<()> {
-        let pending_logs = self.pending_logs.lock().drain().collect::<HashMap<_, _>>();
+        let mut pending_logs = std::mem::take(&mut *self.pending_logs.lock());
 
+        let dropped = self.dropped_since_flush.swap(0, Ordering::Relaxed);
+        if dropped != 0 {
+            pending_logs.push_back((tracing::Level::WARN, LogMessage {
+                timestamp: Timestamp::now(),
+                target: "gnomeutils::logging",
+                shard: None,
+                spans: String::new(),
+                message: format!("{dropped} log messages were dropped as the log buffer was full"),
+                fields: Vec::new(),
+            }));
+        }
+
+        if let Some(file_sink) = &self.options.file_sink {
+            if let Err(err) = self.write_log_file(file_sink, &pending_logs).await {
+                eprintln!("Failed to write to log file {}: {err}", file_sink.path.display());
+            }
+        }
+
+        let pending_logs = pending_logs.into_iter().into_group_map();
+
+        let mut routed_logs = Vec::new();
         for (severity, messages) in pending_logs {
-            let mut chunks: Vec<String> = Vec::with_capacity(messages.len());
-            let pre_chunked: String = messages
-                .into_iter()
-                .map(|(target, log_message)| {
-                    log_message.trim().split('\n').map(move |line| {
-                        format!("`[{}]`: {}\n", target, line)
-                    }).collect::<String>()
-                })
-                .collect();
-
-            for line in pre_chunked.split_inclusive('\n') {
-                for chunk in line.chars().chunks(2000).into_iter().map(Iterator::collect::<String>) {
-                    if let Some(current_chunk) = chunks.last_mut() {
-                        if current_chunk.len() + chunk.len() > 2000 {
-                            chunks.push(chunk);
-                        } else {
-                            current_chunk.push_str(&chunk);
-                        }
-                    } else {
-                        chunks.push(chunk);
-                    }
-                }
+            for log_message in messages {
+                let destinations = self.destinations(severity, log_message.target);
+                routed_logs.extend(destinations.into_iter().map(|webhook_id| ((webhook_id, severity), log_message.clone())));
             }
+        }
 
-            let webhook = if tracing::Level::ERROR >= severity {
-                &self.error_logs
+        for ((webhook_id, severity), messages) in routed_logs.into_iter().into_group_map() {
+            // Deduplicated before formatting, so the timestamp prefix does not make repeats unique
+            let messages: Vec<_> = dedup_messages(messages, LogMessage::dedup_key).into_iter().map(|(log_message, count)| {
+                let target = log_message.target;
+                let formatted = self.format_message(severity, log_message, FormatMode::Discord {include_target: !self.options.embeds});
+                (target, with_repeat_count(formatted, count))
+            }).collect();
+
+            let chunks = if self.options.embeds {
+                self.embed_chunks(severity, messages)
             } else {
-                &self.normal_logs
+                let pre_chunked: String = messages.into_iter().map(|(_, message)| message).collect();
+                self.chunk_text(&pre_chunked, MESSAGE_LENGTH_LIMIT).into_iter().map(ChunkContent::Text).collect()
             };
 
             let severity_str = severity.as_str();
@@ -93,65 +923,339 @@ impl crate::looper::Looper for WebhookLogger {
             webhook_name.push_str(severity_str);
             webhook_name.push(']');
 
-            for chunk in chunks {
-                webhook.execute(&self.http, false, |b| b
-                    .content(chunk)
-                    .username(webhook_name.clone())
-                    .avatar_url(self.level_lookup.get(&severity).cloned().unwrap_or_else(|| String::from(
-                        "https://cdn.discordapp.com/embed/avatars/5.png",
-                    )))
-                ).await?;
-            }
+            let avatar_url = self.level_lookup.get(&severity).cloned().unwrap_or_else(|| String::from(
+                "https://cdn.discordapp.com/embed/avatars/5.png",
+            ));
+
+            self.enqueue(webhook_id, chunks.into_iter().map(|content| PendingChunk {
+                content,
+                attempts: 0,
+                username: webhook_name.clone(),
+                avatar_url: avatar_url.clone(),
+            }));
+        }
+
+        for webhook in self.webhooks.values() {
+            self.deliver(webhook).await;
         }
 
         Ok(())
     }
 }
 
+struct FieldVisitor<'a> {
+    message: &'a mut String,
+    fields: &'a mut Vec<(&'static str, String)>,
+}
+
+impl tracing::field::Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{value:?}").unwrap();
+        } else {
+            self.fields.push((field.name(), format!("{value:?}")));
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+}
+
+/// Records all the fields of a span, ignoring the concept of a message and replacing fields recorded again.
+fn record_span_fields(fields: &mut Vec<(&'static str, String)>, record: impl FnOnce(&mut FieldVisitor<'_>)) {
+    let mut message = String::new();
+    let mut new_fields = Vec::new();
+    record(&mut FieldVisitor {message: &mut message, fields: &mut new_fields});
+
+    if !message.is_empty() {
+        new_fields.push(("message", message));
+    }
+
+    for (name, value) in new_fields {
+        if let Some(field) = fields.iter_mut().find(|(n, _)| *n == name) {
+            field.1 = value;
+        } else {
+            fields.push((name, value));
+        }
+    }
+}
+
+/// Formats a span as `name{key=value key=value}`, or just `name` if it has no fields.
+fn format_span(name: &str, fields: &[(&'static str, String)]) -> String {
+    if fields.is_empty() {
+        return name.to_string()
+    }
+
+    let fields = fields.iter().map(|(name, value)| format!("{name}={value}")).join(" ");
+    format!("{name}{{{fields}}}")
+}
+
+impl WebhookLogger {
+    fn current_span() -> Option<tracing::span::Id> {
+        SPAN_STACK.with(|stack| stack.borrow().last().cloned())
+    }
+
+    /// Returns the formatted path to a span, and the innermost value of the shard field.
+    fn span_path(&self, mut span: Option<tracing::span::Id>) -> (String, Option<String>) {
+        let spans = self.spans.lock();
+
+        let mut path = Vec::new();
+        let mut shard = None;
+        while let Some(data) = span.as_ref().and_then(|id| spans.get(id)) {
+            path.push(format_span(data.metadata.name(), &data.fields));
+            if shard.is_none() {
+                shard = self.shard_field(&data.fields);
+            }
+
+            span.clone_from(&data.parent);
+        }
+
+        (path.into_iter().rev().join(":"), shard)
+    }
+
+    fn shard_field(&self, fields: &[(&'static str, String)]) -> Option<String> {
+        let shard_field = self.options.line_prefix.shard_field?;
+        fields.iter().find(|(name, _)| *name == shard_field).map(|(_, value)| value.clone())
+    }
+}
+
 impl tracing::Subscriber for ArcWrapper<WebhookLogger> {
-    // Hopefully this works
-    fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
-        tracing::span::Id::from_u64(1)
+    fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let id = tracing::span::Id::from_u64(self.next_span_id.fetch_add(1, Ordering::Relaxed));
+        let parent = if let Some(parent) = attrs.parent() {
+            Some(parent.clone())
+        } else if attrs.is_contextual() {
+            WebhookLogger::current_span()
+        } else {
+            None
+        };
+
+        let mut fields = Vec::new();
+        record_span_fields(&mut fields, |visitor| attrs.record(visitor));
+
+        let metadata = attrs.metadata();
+        let mut spans = self.spans.lock();
+
+        // Children keep their parent open, so their path is intact after the parent handle is dropped
+        if let Some(parent_data) = parent.as_ref().and_then(|parent| spans.get_mut(parent)) {
+            parent_data.references += 1;
+        }
+
+        spans.insert(id.clone(), SpanData {metadata, parent, fields, references: 1});
+        id
+    }
+
+    fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        if let Some(data) = self.spans.lock().get_mut(span) {
+            record_span_fields(&mut data.fields, |visitor| values.record(visitor));
+        }
     }
 
     fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
-    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
-    fn enter(&self, _span: &tracing::span::Id) {}
-    fn exit(&self, _span: &tracing::span::Id) {}
 
-    fn event(&self, event: &tracing::Event<'_>) {
-        pub struct StringVisitor<'a> {
-            string: &'a mut String,
-        }
+    fn enter(&self, span: &tracing::span::Id) {
+        SPAN_STACK.with(|stack| stack.borrow_mut().push(span.clone()));
+    }
 
-        impl<'a> tracing::field::Visit for StringVisitor<'a> {
-            fn record_debug(&mut self, _field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-                write!(self.string, "{:?}", value).unwrap();
+    fn exit(&self, span: &tracing::span::Id) {
+        SPAN_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(position) = stack.iter().rposition(|id| id == span) {
+                stack.remove(position);
             }
+        });
+    }
+
+    fn clone_span(&self, span: &tracing::span::Id) -> tracing::span::Id {
+        if let Some(data) = self.spans.lock().get_mut(span) {
+            data.references += 1;
+        }
+
+        span.clone()
+    }
 
-            fn record_str(&mut self, _field: &tracing::field::Field, value: &str) {
-                self.string.push_str(value);
+    fn try_close(&self, span: tracing::span::Id) -> bool {
+        let mut spans = self.spans.lock();
+        let mut closing = Some(span);
+        let mut closed = false;
+
+        // Closing a span releases its reference to its parent, which may then close too
+        while let Some(span) = closing.take() {
+            let Some(data) = spans.get_mut(&span) else {break};
+            data.references -= 1;
+            if data.references != 0 {
+                break
             }
+
+            closing = spans.remove(&span).and_then(|data| data.parent);
+            closed = true;
         }
 
-        let mut message = String::new();
-        event.record(&mut StringVisitor {string: &mut message});
+        closed
+    }
 
-        let metadata = event.metadata();
-        self.pending_logs
-            .lock()
-            .entry(*metadata.level())
-            .or_insert_with(Vec::new)
-            .push((metadata.target(), message));
+    fn current_span(&self) -> tracing_core::span::Current {
+        let Some(id) = WebhookLogger::current_span() else {
+            return tracing_core::span::Current::none();
+        };
+
+        match self.spans.lock().get(&id) {
+            Some(data) => tracing_core::span::Current::new(id, data.metadata),
+            None => tracing_core::span::Current::none(),
+        }
+    }
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        let parent = if let Some(parent) = event.parent() {
+            Some(parent.clone())
+        } else if event.is_contextual() {
+            WebhookLogger::current_span()
+        } else {
+            None
+        };
+
+        let (spans, shard) = self.span_path(parent);
+        self.push_event(event, spans, shard);
     }
 
     fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        self.is_enabled(metadata)
+    }
+}
+
+impl WebhookLogger {
+    /// Replaces the target directives at runtime, such as from an owner command.
+    pub fn set_directives(&self, directives: LogDirectives) {
+        *self.directives.write() = directives;
+
+        // Callsites cache if they are enabled, so must be told to check again
+        tracing::callsite::rebuild_interest_cache();
+    }
+
+    fn is_enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
         // Ordered by verbosity
-        if ["gnomeutils", self.log_prefix].into_iter().any(|t| metadata.target().starts_with(t)) {
-            self.max_verbosity >= *metadata.level()
-        } else {
-            tracing::Level::WARN >= *metadata.level()
+        self.directives.read().max_level(metadata.target()) >= *metadata.level()
+    }
+
+    fn push_event(&self, event: &tracing::Event<'_>, spans: String, shard: Option<String>) {
+        let mut message = String::new();
+        let mut fields = Vec::new();
+        event.record(&mut FieldVisitor {message: &mut message, fields: &mut fields});
+
+        let metadata = event.metadata();
+        self.push_log(*metadata.level(), LogMessage {
+            timestamp: Timestamp::now(),
+            target: metadata.target(),
+            shard, spans, message, fields,
+        });
+    }
+
+    fn push_log(&self, level: tracing::Level, message: LogMessage) {
+        let mut pending_logs = self.pending_logs.lock();
+        if self.options.max_pending_logs.is_some_and(|max| pending_logs.len() >= max) {
+            let dropped_level = match self.options.overflow_policy {
+                OverflowPolicy::DropOldest => pending_logs.pop_front().map(|(level, _)| level),
+                OverflowPolicy::DropLowestSeverity => {
+                    // More verbose levels compare as greater, so the maximum is the least severe
+                    match pending_logs.iter().map(|(level, _)| *level).max() {
+                        Some(lowest) if lowest >= level => pending_logs
+                            .iter()
+                            .position(|(level, _)| *level == lowest)
+                            .and_then(|index| pending_logs.remove(index))
+                            .map(|(level, _)| level),
+                        _ => {
+                            self.record_dropped(level);
+                            return
+                        }
+                    }
+                }
+            };
+
+            if let Some(dropped_level) = dropped_level {
+                self.record_dropped(dropped_level);
+            }
         }
+
+        pending_logs.push_back((level, message));
+    }
+
+    fn record_dropped(&self, level: tracing::Level) {
+        self.dropped_since_flush.fetch_add(1, Ordering::Relaxed);
+
+        let mut dropped_logs = self.dropped_logs.lock();
+        dropped_logs.total += 1;
+        *dropped_logs.by_level.entry(level).or_default() += 1;
+    }
+
+    /// Returns how many log messages have been dropped due to the buffer of pending logs being full.
+    #[must_use]
+    pub fn dropped_logs(&self) -> DroppedLogs {
+        self.dropped_logs.lock().clone()
+    }
+}
+
+
+/// The fields of a span, stored in its extensions when used as a [`tracing_subscriber::Layer`].
+struct SpanFields(Vec<(&'static str, String)>);
+
+impl ArcWrapper<WebhookLogger> {
+    /// Returns the logger as a [`tracing_subscriber::Layer`] filtered by its own verbosity settings,
+    /// so it can be composed with other layers such as stdout or file logging.
+    #[must_use]
+    pub fn layer<S>(&self) -> Filtered<Self, Self, S>
+    where S: tracing::Subscriber + for<'a> LookupSpan<'a>
+    {
+        self.clone().with_filter(self.clone())
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for ArcWrapper<WebhookLogger>
+where S: tracing::Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: layer::Context<'_, S>) {
+        let mut fields = Vec::new();
+        record_span_fields(&mut fields, |visitor| attrs.record(visitor));
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(SpanFields(fields)) = extensions.get_mut() {
+                record_span_fields(fields, |visitor| values.record(visitor));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: layer::Context<'_, S>) {
+        let mut shard = None;
+        let spans = ctx.event_scope(event).map(|scope| scope.from_root().map(|span| {
+            let extensions = span.extensions();
+            let fields = extensions.get::<SpanFields>().map_or(&[][..], |SpanFields(fields)| fields);
+
+            // Iterating from the root, so the innermost span with the field wins
+            if let Some(value) = self.shard_field(fields) {
+                shard = Some(value);
+            }
+
+            format_span(span.name(), fields)
+        }).join(":")).unwrap_or_default();
+
+        self.push_event(event, spans, shard);
+    }
+}
+
+impl<S> layer::Filter<S> for ArcWrapper<WebhookLogger> {
+    fn enabled(&self, metadata: &tracing::Metadata<'_>, _ctx: &layer::Context<'_, S>) -> bool {
+        self.is_enabled(metadata)
     }
 }
 
@@ -170,3 +1274,269 @@ impl<T> std::ops::Deref for ArcWrapper<T> {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(id: u64) -> Webhook {
+        serenity::json::prelude::from_value(serenity::json::json!({"id": id.to_string(), "type": 1, "token": "token"})).unwrap()
+    }
+
+    fn is_balanced(chunk: &str) -> bool {
+        chunk.lines().filter(|line| line.starts_with("```")).count() % 2 == 0
+    }
+
+    fn log_message(timestamp: i64, message: &str) -> LogMessage {
+        LogMessage {
+            timestamp: Timestamp::from_unix_timestamp(timestamp).unwrap(),
+            target: "target",
+            shard: None,
+            spans: String::new(),
+            message: String::from(message),
+            fields: vec![("key", String::from("value"))],
+        }
+    }
+
+    #[test]
+    fn dedup_messages_counts_repeats() {
+        let messages = ["a", "b", "a", "a"];
+        assert_eq!(dedup_messages(messages, |message| *message), [("a", 3), ("b", 1)]);
+    }
+
+    #[test]
+    fn dedup_messages_leaves_unique_messages() {
+        let messages = ["a", "b", "c"];
+        assert_eq!(dedup_messages(messages, |message| *message), [("a", 1), ("b", 1), ("c", 1)]);
+    }
+
+    #[test]
+    fn dedup_messages_ignores_timestamps() {
+        let messages = [log_message(0, "a"), log_message(1, "b"), log_message(2, "a")];
+        let deduped = dedup_messages(messages, LogMessage::dedup_key);
+
+        let summary: Vec<_> = deduped.iter().map(|(message, count)| (message.message.as_str(), *count)).collect();
+        assert_eq!(summary, [("a", 2), ("b", 1)]);
+        // The first occurrence is kept, along with its timestamp
+        assert_eq!(deduped[0].0.timestamp.unix_timestamp(), 0);
+    }
+
+    #[test]
+    fn dedup_messages_compares_fields() {
+        let mut other_fields = log_message(0, "a");
+        other_fields.fields[0].1 = String::from("other");
+
+        let deduped = dedup_messages([log_message(0, "a"), other_fields], LogMessage::dedup_key);
+        assert_eq!(deduped.len(), 2);
+    }
+
+    #[test]
+    fn with_repeat_count_appends_to_first_line() {
+        assert_eq!(with_repeat_count(String::from("a\n"), 1), "a\n");
+        assert_eq!(with_repeat_count(String::from("a\n"), 3), "a (x3)\n");
+        assert_eq!(
+            with_repeat_count(String::from("`[target]`: first\n```\nkey=value\n```\n"), 2),
+            "`[target]`: first (x2)\n```\nkey=value\n```\n"
+        );
+    }
+
+    #[test]
+    fn repeats_with_timestamp_prefixes_are_counted() {
+        let logger = logger(LoggerOptions {
+            line_prefix: LinePrefix {timestamp: true, ..LinePrefix::default()},
+            ..LoggerOptions::default()
+        });
+
+        let messages = [log_message(0, "a"), log_message(60, "a")];
+        let formatted: Vec<_> = dedup_messages(messages, LogMessage::dedup_key).into_iter()
+            .map(|(message, count)| with_repeat_count(logger.format_message(tracing::Level::INFO, message, FormatMode::Discord {include_target: true}), count))
+            .collect();
+
+        assert_eq!(formatted, ["<t:0:T> `[target]`: a key=value (x2)\n"]);
+    }
+
+    #[test]
+    fn chunk_lines_fills_chunks_to_limit() {
+        // 1000 + 1000 characters, exactly the limit
+        let line = format!("{}\n", "a".repeat(999));
+        assert_eq!(chunk_lines(&line.repeat(2), 2000), [line.repeat(2)]);
+
+        // One character over the limit moves the second line into its own chunk
+        let longer_line = format!("{}\n", "a".repeat(1000));
+        assert_eq!(chunk_lines(&format!("{line}{longer_line}"), 2000), [line, longer_line]);
+    }
+
+    #[test]
+    fn chunk_lines_splits_long_lines() {
+        let text = "a".repeat(4500);
+        let chunks = chunk_lines(&text, 2000);
+
+        assert_eq!(chunks.iter().map(String::len).collect::<Vec<_>>(), [2000, 2000, 500]);
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn chunk_lines_counts_characters() {
+        // 2000 characters, but 4000 bytes
+        let text = "é".repeat(2000);
+        assert_eq!(chunk_lines(&text, 2000), [text]);
+    }
+
+    #[test]
+    fn chunk_lines_rebalances_code_blocks() {
+        let mut text = String::from("message\n```\n");
+        for i in 0..200 {
+            writeln!(text, "field_{i}=value").unwrap();
+        }
+        text.push_str("```\n");
+
+        let chunks = chunk_lines(&text, 2000);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 2000);
+            assert!(is_balanced(chunk), "unbalanced chunk: {chunk}");
+        }
+
+        // Only the added fences differ from the original text
+        let rejoined = chunks.concat().replace("```\n```\n", "");
+        assert_eq!(rejoined, text);
+    }
+
+    #[test]
+    fn chunk_lines_rebalances_long_lines_in_code_blocks() {
+        let text = format!("```\n{}\n```\n", "a".repeat(5000));
+        let chunks = chunk_lines(&text, 2000);
+
+        assert_eq!(chunks.len(), 3);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 2000);
+            assert!(is_balanced(chunk), "unbalanced chunk: {chunk}");
+        }
+    }
+
+    fn logger(options: LoggerOptions) -> ArcWrapper<WebhookLogger> {
+        WebhookLogger::with_routes(
+            Arc::new(Http::new("")),
+            "test",
+            "Test",
+            tracing::Level::TRACE,
+            Vec::new(),
+            webhook(1),
+            options,
+        )
+    }
+
+    fn field(name_len: usize, value_len: usize) -> (String, String) {
+        ("n".repeat(name_len), "v".repeat(value_len))
+    }
+
+    fn message_len(embeds: &[Vec<(String, String)>], title_len: usize) -> usize {
+        embeds.iter()
+            .map(|fields| title_len + fields.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>())
+            .sum()
+    }
+
+    #[test]
+    fn split_embeds_limits_fields_per_embed() {
+        let messages = split_embeds(vec![field(1, 1); 60], 5);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].iter().map(Vec::len).collect::<Vec<_>>(), [25, 25, 10]);
+    }
+
+    #[test]
+    fn split_embeds_limits_embeds_per_message() {
+        let messages = split_embeds(vec![field(1, 1); 300], 5);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].len(), EMBEDS_PER_MESSAGE);
+        assert_eq!(messages[1].len(), 2);
+        assert_eq!(messages.iter().flatten().map(Vec::len).sum::<usize>(), 300);
+    }
+
+    #[test]
+    fn split_embeds_limits_total_length() {
+        // Five of these fit in 6000 characters, but not with the title
+        let messages = split_embeds(vec![field(200, 1000); 5], 5);
+        assert_eq!(messages.iter().map(Vec::len).collect::<Vec<_>>(), [1, 1]);
+        assert_eq!(messages[0][0].len(), 4);
+        assert_eq!(message_len(&messages[0], 5), 4805);
+
+        let messages = split_embeds(vec![field(200, 1000); 5], 0);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(message_len(&messages[0], 0), EMBED_TOTAL_LIMIT);
+
+        // Each new embed counts the title again
+        let messages = split_embeds(vec![field(1, 1); 60], 2000);
+        for embeds in &messages {
+            assert!(message_len(embeds, 2000) <= EMBED_TOTAL_LIMIT);
+        }
+        assert_eq!(messages.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+    }
+
+    #[test]
+    fn split_embeds_empty() {
+        assert!(split_embeds(Vec::new(), 5).is_empty());
+    }
+
+    #[test]
+    fn chunk_text_fits_embed_field_values() {
+        let text = "`[target]`: message\n".repeat(300);
+        for code_blocks in [false, true] {
+            let logger = logger(LoggerOptions {code_blocks, ..LoggerOptions::default()});
+            let chunks = logger.chunk_text(&text, EMBED_FIELD_VALUE_LIMIT);
+
+            assert!(chunks.len() > 1);
+            for chunk in &chunks {
+                assert!(chunk.chars().count() <= EMBED_FIELD_VALUE_LIMIT);
+            }
+
+            let fields = chunks.into_iter().map(|value| (String::from("target"), value)).collect::<Vec<_>>();
+            for embeds in split_embeds(fields, 5) {
+                assert!(message_len(&embeds, 5) <= EMBED_TOTAL_LIMIT);
+            }
+        }
+    }
+
+    #[test]
+    fn current_span_follows_entered_spans() {
+        let logger = logger(LoggerOptions::default());
+
+        tracing::subscriber::with_default(logger, || {
+            assert!(tracing::Span::current().is_none());
+
+            let outer = tracing::info_span!("outer");
+            let _outer = outer.enter();
+            assert_eq!(tracing::Span::current().metadata().map(tracing::Metadata::name), Some("outer"));
+
+            {
+                let inner = tracing::info_span!("inner");
+                let _inner = inner.enter();
+                assert_eq!(tracing::Span::current().metadata().map(tracing::Metadata::name), Some("inner"));
+            }
+
+            assert_eq!(tracing::Span::current().metadata().map(tracing::Metadata::name), Some("outer"));
+        });
+    }
+
+    #[test]
+    fn child_spans_keep_parents_open() {
+        let logger = logger(LoggerOptions {
+            line_prefix: LinePrefix {shard_field: Some("shard_id"), ..LinePrefix::default()},
+            ..LoggerOptions::default()
+        });
+
+        tracing::subscriber::with_default(logger.clone(), || {
+            let shard = tracing::info_span!("shard", shard_id = 3);
+            let handler = tracing::info_span!(parent: &shard, "handler");
+            drop(shard);
+
+            let (spans, shard) = logger.span_path(handler.id());
+            assert_eq!(spans, "shard{shard_id=3}:handler");
+            assert_eq!(shard.as_deref(), Some("3"));
+
+            drop(handler);
+            assert!(logger.spans.lock().is_empty());
+        });
+    }
+}
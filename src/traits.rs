@@ -2,6 +2,8 @@ use anyhow::Result;
 
 #[cfg(feature = "poise")]
 use crate::{serenity, GnomeData};
+#[cfg(feature = "error_handling")]
+use crate::EmbedTheme;
 
 #[cfg(feature = "i18n")]
 pub trait OptionGettext<'a> {
@@ -44,6 +46,12 @@ pub trait PoiseContextExt {
     fn current_catalog(&self) -> Option<&gettext::Catalog>;
     #[cfg(feature = "error_handling")]
     async fn send_error(&self, error: &str, fix: Option<&str>) -> Result<Option<poise::ReplyHandle<'_>>>;
+    #[cfg(feature = "error_handling")]
+    async fn send_success(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>>;
+    #[cfg(feature = "error_handling")]
+    async fn send_warning(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>>;
+    #[cfg(feature = "error_handling")]
+    async fn send_info(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>>;
 
     async fn author_permissions(&self) -> Result<serenity::Permissions>;
 }
@@ -77,77 +85,245 @@ impl<D: AsRef<GnomeData> + Send + Sync, E: Send + Sync> PoiseContextExt for pois
     }
 
     async fn author_permissions(&self) -> Result<serenity::Permissions> {
-        let ctx_discord = self.discord();
+        // Interactions come with the member's permissions in the channel, including overwrites
+        if let poise::Context::Application(ctx) = self {
+            if let poise::ApplicationCommandOrAutocompleteInteraction::ApplicationCommand(interaction) = ctx.interaction {
+                if let Some(permissions) = interaction.member.as_ref().and_then(|m| m.permissions) {
+                    return Ok(permissions)
+                }
+            }
+        }
 
-        match ctx_discord.cache.channel(self.channel_id()).try_unwrap()? {
-            serenity::Channel::Guild(channel) => {
-                let guild = channel.guild(&ctx_discord.cache).try_unwrap()?;
-                let member = guild.member(ctx_discord, self.author()).await?;
+        let ctx_discord = self.discord();
+        let channel = match resolve_channel(ctx_discord, self.guild_id(), self.channel_id()).await? {
+            serenity::Channel::Guild(channel) => permission_channel(ctx_discord, channel).await?,
+            _ => return Ok(DM_PERMISSIONS),
+        };
 
-                Ok(guild.user_permissions_in(&channel, &member)?)
-            }
-            _ => {
-                Ok(((serenity::Permissions::from_bits_truncate(0b111_1100_1000_0000_0000_0111_1111_1000_0100_0000)
-                    | serenity::Permissions::SEND_MESSAGES)
-                    - serenity::Permissions::SEND_TTS_MESSAGES)
-                    - serenity::Permissions::MANAGE_MESSAGES)
-            }
+        let author = self.author();
+        if let Some(guild) = channel.guild(&ctx_discord.cache) {
+            let member = guild.member(ctx_discord, author.id).await?;
+            Ok(guild.user_permissions_in(&channel, &member)?)
+        } else {
+            let guild = channel.guild_id.to_partial_guild(ctx_discord).await?;
+            let member = guild.member(ctx_discord, author.id).await?;
+            Ok(guild.user_permissions_in(&channel, &member)?)
         }
     }
 
     #[cfg(feature = "error_handling")]
     async fn send_error(&self, error: &str, fix: Option<&str>) -> Result<Option<poise::ReplyHandle<'_>>> {
-        let author = self.author();
-        let ctx_discord = self.discord();
+        let fix = fix.unwrap_or_else(|| self.gettext("get in contact with us via the support server"));
+        let description = self
+            .gettext("Sorry but {error}, to fix this, please {fix}!")
+            .replace("{error}", error)
+            .replace("{fix}", fix);
 
-        let m;
-        let (name, avatar_url) = match self.channel_id().to_channel(ctx_discord).await? {
-            serenity::Channel::Guild(channel) => {
-                let permissions = channel.permissions_for_user(ctx_discord, ctx_discord.cache.current_user_id())?;
-
-                if !permissions.send_messages() {
-                    return Ok(None);
-                };
-
-                if !permissions.embed_links() {
-                    return self.send(|b| {b
-                        .ephemeral(true)
-                        .content("An Error Occurred! Please give me embed links permissions so I can tell you more!")
-                    }).await.map(Some).map_err(Into::into)
-                };
-
-                match channel.guild_id.member(ctx_discord, author.id).await {
-                    Ok(member) => {
-                        m = member;
-                        (m.display_name(), m.face())
-                    },
-                    Err(_) => (std::borrow::Cow::Borrowed(&author.name), author.face()),
-                }
-            }
-            serenity::Channel::Private(_) => (std::borrow::Cow::Borrowed(&author.name), author.face()),
-            _ => unreachable!(),
-        };
+        send_themed(self, EmbedKind::Error, description).await
+    }
+
+    #[cfg(feature = "error_handling")]
+    async fn send_success(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>> {
+        send_themed(self, EmbedKind::Success, message.to_string()).await
+    }
+
+    #[cfg(feature = "error_handling")]
+    async fn send_warning(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>> {
+        send_themed(self, EmbedKind::Warning, message.to_string()).await
+    }
+
+    #[cfg(feature = "error_handling")]
+    async fn send_info(&self, message: &str) -> Result<Option<poise::ReplyHandle<'_>>> {
+        send_themed(self, EmbedKind::Info, message.to_string()).await
+    }
+}
+
+#[cfg(feature = "error_handling")]
+#[derive(Clone, Copy)]
+enum EmbedKind {
+    Error,
+    Success,
+    Warning,
+    Info,
+}
+
+#[cfg(feature = "error_handling")]
+impl EmbedKind {
+    const fn colour_and_title(self, theme: &EmbedTheme) -> (u32, &'static str) {
+        match self {
+            Self::Error => (theme.error_colour, theme.error_title),
+            Self::Success => (theme.success_colour, theme.success_title),
+            Self::Warning => (theme.warning_colour, theme.warning_title),
+            Self::Info => (theme.info_colour, theme.info_title),
+        }
+    }
+}
+
+/// The permissions everyone has in DMs, as there are no roles or overwrites.
+#[cfg(feature = "poise")]
+const DM_PERMISSIONS: serenity::Permissions = serenity::Permissions::from_bits_truncate(
+    serenity::Permissions::ADD_REACTIONS.bits()
+    | serenity::Permissions::SEND_MESSAGES.bits()
+    | serenity::Permissions::EMBED_LINKS.bits()
+    | serenity::Permissions::ATTACH_FILES.bits()
+    | serenity::Permissions::READ_MESSAGE_HISTORY.bits()
+    | serenity::Permissions::MENTION_EVERYONE.bits()
+    | serenity::Permissions::USE_EXTERNAL_EMOJIS.bits()
+    | serenity::Permissions::USE_SLASH_COMMANDS.bits()
+    | serenity::Permissions::MANAGE_THREADS.bits()
+    | serenity::Permissions::CREATE_PUBLIC_THREADS.bits()
+    | serenity::Permissions::CREATE_PRIVATE_THREADS.bits()
+    | serenity::Permissions::USE_EXTERNAL_STICKERS.bits()
+    | serenity::Permissions::SEND_MESSAGES_IN_THREADS.bits()
+);
+
+#[cfg(feature = "poise")]
+const fn is_thread(kind: serenity::ChannelType) -> bool {
+    matches!(kind, serenity::ChannelType::PublicThread | serenity::ChannelType::PrivateThread | serenity::ChannelType::NewsThread)
+}
+
+/// Resolves a channel from the cache, including guild threads, before falling back to HTTP.
+#[cfg(feature = "poise")]
+pub(crate) async fn resolve_channel(ctx_discord: &serenity::Context, guild_id: Option<serenity::GuildId>, channel_id: serenity::ChannelId) -> Result<serenity::Channel> {
+    if let Some(channel) = ctx_discord.cache.channel(channel_id) {
+        return Ok(channel)
+    }
+
+    let thread = guild_id.and_then(|guild_id| ctx_discord.cache.guild_field(guild_id, |g| {
+        g.threads.iter().find(|t| t.id == channel_id).cloned()
+    }));
+
+    match thread.flatten() {
+        Some(thread) => Ok(serenity::Channel::Guild(thread)),
+        None => Ok(channel_id.to_channel(ctx_discord).await?),
+    }
+}
+
+/// The parent channel to take permission overwrites from, if the channel is a thread.
+#[cfg(feature = "poise")]
+fn thread_parent_id(channel: &serenity::GuildChannel) -> Option<serenity::ChannelId> {
+    channel.parent_id.filter(|_| is_thread(channel.kind))
+}
+
+/// Threads use the permission overwrites of their parent channel, so this resolves the parent of threads.
+#[cfg(feature = "poise")]
+async fn permission_channel(ctx_discord: &serenity::Context, channel: serenity::GuildChannel) -> Result<serenity::GuildChannel> {
+    if let Some(parent_id) = thread_parent_id(&channel) {
+        if let serenity::Channel::Guild(parent) = resolve_channel(ctx_discord, Some(channel.guild_id), parent_id).await? {
+            return Ok(parent)
+        }
+    }
+
+    Ok(channel)
+}
+
+/// Sends an embed styled by [`GnomeData::theme`], falling back to plain text without embed links
+/// permissions, or to the author's DMs if the channel cannot be sent to.
+#[cfg(feature = "error_handling")]
+async fn send_themed<'a, D: AsRef<GnomeData> + Send + Sync, E: Send + Sync>(
+    ctx: &'a poise::Context<'_, D, E>,
+    kind: EmbedKind,
+    description: String
+) -> Result<Option<poise::ReplyHandle<'a>>> {
+    let author = ctx.author();
+    let ctx_discord = ctx.discord();
+    let data = ctx.data().as_ref();
+
+    let (colour, title) = kind.colour_and_title(&data.theme);
+    let title = ctx.gettext(title);
+    let footer = ctx.gettext(data.theme.footer).replace("{server_invite}", &data.main_server_invite);
+
+    let mut can_send = true;
+    let mut guild_id = None;
+    if let Ok(serenity::Channel::Guild(channel)) = resolve_channel(ctx_discord, ctx.guild_id(), ctx.channel_id()).await {
+        guild_id = Some(channel.guild_id);
+
+        // Interaction responses are not restricted by channel permissions
+        if let poise::Context::Prefix(_) = ctx {
+            let bot_user_id = ctx_discord.cache.current_user_id();
+            let permissions = permission_channel(ctx_discord, channel.clone()).await?.permissions_for_user(ctx_discord, bot_user_id)?;
+            can_send = if is_thread(channel.kind) {
+                permissions.contains(serenity::Permissions::SEND_MESSAGES_IN_THREADS)
+            } else {
+                permissions.send_messages()
+            };
 
-        match self.send(|b| b
-            .ephemeral(true)
-            .embed(|e| e
-                .colour(crate::RED)
-                .title("An Error Occurred!")
-                .description(format!(
-                    "Sorry but {}, to fix this, please {}!", error,
-                    fix.unwrap_or("get in contact with us via the support server"),
-                ))
-                .author(|a| a
-                    .name(name.into_owned())
-                    .icon_url(avatar_url)
-                )
-                .footer(|f| f.text(format!(
-                    "Support Server: {}", self.data().as_ref().main_server_invite
-                )))
-            )
-        ).await {
-            Ok(handle) => Ok(Some(handle)),
-            Err(_) => Ok(None)
+            if can_send && !permissions.embed_links() {
+                return ctx.send(|b| {b
+                    .ephemeral(true)
+                    .content(format!("**{title}**\n{description}"))
+                }).await.map(Some).map_err(Into::into)
+            };
         }
     }
+
+    let (name, avatar_url) = match guild_id {
+        Some(guild_id) => match guild_id.member(ctx_discord, author.id).await {
+            Ok(member) => (member.display_name().into_owned(), member.face()),
+            Err(_) => (author.name.clone(), author.face()),
+        },
+        None => (author.name.clone(), author.face()),
+    };
+
+    let mut embed = serenity::CreateEmbed::default();
+    embed
+        .colour(colour)
+        .title(title)
+        .description(description)
+        .author(|a| a
+            .name(name)
+            .icon_url(avatar_url)
+        )
+        .footer(|f| f.text(footer));
+
+    if !can_send {
+        // DMs may be closed, and there is nowhere else to send the error
+        if let Err(err) = author.direct_message(ctx_discord, |m| m.set_embed(embed)).await {
+            tracing::debug!("Could not DM {} after failing to send to their channel: {:?}", author.id, err);
+        }
+
+        return Ok(None)
+    }
+
+    match ctx.send(|b| b
+        .ephemeral(true)
+        .embed(|e| {*e = embed; e})
+    ).await {
+        Ok(handle) => Ok(Some(handle)),
+        Err(_) => Ok(None)
+    }
+}
+
+#[cfg(all(test, feature = "poise"))]
+mod tests {
+    use super::*;
+    use serenity::json::{json, prelude::from_value};
+
+    fn guild_channel(kind: u8, parent_id: Option<&str>) -> serenity::GuildChannel {
+        from_value(json!({"id": "2", "guild_id": "1", "type": kind, "name": "channel", "parent_id": parent_id})).unwrap()
+    }
+
+    #[test]
+    fn dm_permissions_match_old_bitmask() {
+        let old_bitmask = ((serenity::Permissions::from_bits_truncate(0b111_1100_1000_0000_0000_0111_1111_1000_0100_0000)
+            | serenity::Permissions::SEND_MESSAGES)
+            - serenity::Permissions::SEND_TTS_MESSAGES)
+            - serenity::Permissions::MANAGE_MESSAGES;
+
+        assert_eq!(DM_PERMISSIONS, old_bitmask);
+    }
+
+    #[test]
+    fn threads_use_parent_permissions() {
+        let parent_id = Some(serenity::ChannelId(3));
+
+        // Public, private, and news threads
+        for kind in [11, 12, 10] {
+            assert_eq!(thread_parent_id(&guild_channel(kind, Some("3"))), parent_id);
+        }
+
+        // A text channel has its own overwrites, its parent is only the category it is in
+        assert_eq!(thread_parent_id(&guild_channel(0, Some("3"))), None);
+        assert_eq!(thread_parent_id(&guild_channel(0, None)), None);
+    }
 }